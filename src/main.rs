@@ -1,15 +1,21 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{IsTerminal, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 /// CLI arguments structure using clap
 #[derive(Parser, Debug)]
 #[command(name = "whichport")]
 #[command(version)]
-#[command(about = "Query listening TCP ports and their processes", long_about = None)]
+#[command(about = "Query listening TCP/UDP ports and their processes", long_about = None)]
 struct Cli {
     /// Port numbers to query (1-65535)
     #[arg(value_parser = parse_port)]
@@ -19,13 +25,345 @@ struct Cli {
     #[arg(long)]
     all: bool,
 
+    /// List listening Unix domain sockets instead of ports
+    #[arg(long, conflicts_with_all = ["ports", "all", "proto", "udp"])]
+    unix: bool,
+
     /// Output in JSON format
     #[arg(long)]
     json: bool,
 
+    /// Print the JSON Schema of --json output and exit
+    #[arg(long, exclusive = true)]
+    print_schema: bool,
+
     /// Include metadata in text output
     #[arg(long)]
     verbose: bool,
+
+    /// Output layout: one line per listener, an aligned table, CSV/TSV rows, or NDJSON records
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "json")]
+    format: OutputFormat,
+
+    /// Columns for --format table/csv/tsv, comma separated (csv/tsv default to all columns)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    columns: Option<Vec<Column>>,
+
+    /// Emit one row per endpoint instead of joining endpoints (--format table/csv/tsv)
+    #[arg(long, conflicts_with = "json")]
+    per_endpoint: bool,
+
+    /// Render each listener through a template, e.g. '{port} {command}{?pid} ({pid}){/pid}'
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["json", "format", "columns", "per_endpoint"]
+    )]
+    template: Option<String>,
+
+    /// Read the --template string from a file
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["template", "json", "format", "columns", "per_endpoint"]
+    )]
+    template_file: Option<PathBuf>,
+
+    /// Transport protocol(s) to query
+    #[arg(long, value_enum, default_value_t = ProtoFilter::Tcp)]
+    proto: ProtoFilter,
+
+    /// Shorthand for --proto udp
+    #[arg(long, conflicts_with = "proto")]
+    udp: bool,
+
+    /// Show established TCP connections for each listener (root network namespace only)
+    #[arg(long, conflicts_with_all = ["unix", "netns"])]
+    connections: bool,
+
+    /// Connect to TCP listeners with an unknown role and identify their protocol
+    #[arg(long, conflicts_with_all = ["unix", "netns"])]
+    probe: bool,
+
+    /// Fetch / from each TCP listener and fingerprint HTTP servers (status, headers, title)
+    #[arg(long, conflicts_with_all = ["unix", "netns"])]
+    http: bool,
+
+    /// Inspect the certificate served by each TLS listener (requires the openssl CLI)
+    #[arg(long, conflicts_with_all = ["unix", "netns"])]
+    tls: bool,
+
+    /// Network namespace to query: a name under /run/netns, a PID, or "all" (Linux only)
+    #[arg(long, value_name = "NAME|PID|all", value_parser = parse_netns_selector, conflicts_with = "unix")]
+    netns: Option<NetnsSelector>,
+
+    /// Role rules file (TOML, or JSON by .json extension); defaults to ~/.config/whichport/rules.toml
+    #[arg(long, value_name = "PATH")]
+    rules: Option<PathBuf>,
+
+    /// Truncate cmdline/exe/cwd in --verbose text output to N characters (0 disables)
+    #[arg(long, value_name = "N", default_value_t = 120)]
+    truncate: usize,
+}
+
+/// Network namespace selection for --netns
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NetnsSelector {
+    All,
+    Pid(u32),
+    Name(String),
+}
+
+/// Parse a --netns value; numeric values select the namespace of that PID
+fn parse_netns_selector(s: &str) -> Result<NetnsSelector, String> {
+    if s.is_empty() {
+        return Err("network namespace must not be empty".to_string());
+    }
+    if s == "all" {
+        return Ok(NetnsSelector::All);
+    }
+    if let Ok(pid) = s.parse::<u32>() {
+        return Ok(NetnsSelector::Pid(pid));
+    }
+    if s.contains('/') {
+        return Err(format!("invalid network namespace name: {s}"));
+    }
+    Ok(NetnsSelector::Name(s.to_string()))
+}
+
+/// Output layout for --format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// `port tcp/N: cmd (pid X, user Y) on [...] | role (confidence)`, stable for scripts
+    Text,
+    /// Aligned columns fitted to the terminal width
+    Table,
+    /// Comma-separated values with a header row (RFC 4180 quoting)
+    Csv,
+    /// Tab-separated values with a header row
+    Tsv,
+    /// One JSON object per line: a meta record, then one record per listener
+    Ndjson,
+}
+
+/// Column of --format table/csv/tsv output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Column {
+    Port,
+    Proto,
+    Pid,
+    User,
+    Command,
+    Address,
+    Role,
+    Confidence,
+    Service,
+    Unit,
+    Container,
+    Netns,
+    Listening,
+}
+
+impl Column {
+    fn header(self) -> &'static str {
+        match self {
+            Column::Port => "PORT",
+            Column::Proto => "PROTO",
+            Column::Pid => "PID",
+            Column::User => "USER",
+            Column::Command => "COMMAND",
+            Column::Address => "ADDRESS",
+            Column::Role => "ROLE",
+            Column::Confidence => "CONFIDENCE",
+            Column::Service => "SERVICE",
+            Column::Unit => "UNIT",
+            Column::Container => "CONTAINER",
+            Column::Netns => "NETNS",
+            Column::Listening => "LISTENING",
+        }
+    }
+
+    /// Free-text columns that may be cut to fit the terminal; identifiers are never cut
+    fn shrinkable(self) -> bool {
+        !matches!(
+            self,
+            Column::Port | Column::Proto | Column::Pid | Column::Confidence | Column::Listening
+        )
+    }
+
+    /// Numeric columns are right-aligned
+    fn right_aligned(self) -> bool {
+        matches!(self, Column::Port | Column::Pid)
+    }
+
+    /// Cell value for a listener; `endpoint` replaces the joined endpoints (--per-endpoint).
+    /// None means the listener has no such value.
+    fn value(self, listener: &AggregatedListener, endpoint: Option<&str>) -> Option<String> {
+        match self {
+            Column::Port => match listener.protocol {
+                Protocol::Unix => None,
+                _ => Some(listener.port.to_string()),
+            },
+            Column::Proto => Some(listener.protocol.as_str().to_string()),
+            Column::Pid => listener.pid.map(|pid| pid.to_string()),
+            Column::User => Some(listener.user.clone()),
+            Column::Command => Some(listener.command.clone()),
+            Column::Address => {
+                Some(endpoint.map_or_else(|| listener.endpoints.join(", "), str::to_string))
+            }
+            Column::Role => Some(listener.role.description.to_string()),
+            Column::Confidence => Some(listener.role.confidence.as_str().to_string()),
+            Column::Service => listener.service.clone(),
+            Column::Unit => listener.unit.clone(),
+            Column::Container => listener.container.as_ref().map(container_display),
+            Column::Netns => listener.netns.clone(),
+            Column::Listening => Some("yes".to_string()),
+        }
+    }
+}
+
+/// Columns shown by --format table unless --columns is given; csv/tsv show every column
+/// and always include `listening`
+const DEFAULT_COLUMNS: [Column; 7] = [
+    Column::Port,
+    Column::Proto,
+    Column::Pid,
+    Column::User,
+    Column::Command,
+    Column::Address,
+    Column::Role,
+];
+
+/// Narrowest a shrinkable table column gets when fitting the terminal width
+const MIN_COLUMN_WIDTH: usize = 8;
+
+/// Gap between table columns
+const COLUMN_GAP: &str = "  ";
+
+/// Piece of a parsed --template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplateNode {
+    Text(String),
+    /// `{role.description}`: a dotted path into the listener's JSON fields
+    Field(Vec<String>),
+    /// `{?pid}...{/pid}` renders the body when the field is set; `{!pid}` when it is not
+    Section {
+        path: Vec<String>,
+        inverted: bool,
+        body: Vec<TemplateNode>,
+    },
+}
+
+/// Output template for --template / --template-file
+///
+/// Fields use the --json names, so `{port}`, `{endpoints}`, `{role.confidence}` and
+/// `{container.id}` all work. `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    nodes: Vec<TemplateNode>,
+}
+
+impl Template {
+    fn parse(source: &str) -> Result<Self, String> {
+        let schema: serde_json::Value = serde_json::from_str(OUTPUT_SCHEMA).unwrap_or_default();
+        // Stack of open sections: (path, inverted, nodes before the section)
+        let mut open: Vec<(Vec<String>, bool, Vec<TemplateNode>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err("unmatched '}' (use '}}' for a literal brace)".to_string()),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => return Err(format!("unclosed tag '{{{tag}'")),
+                        }
+                    }
+                    if !text.is_empty() {
+                        nodes.push(TemplateNode::Text(std::mem::take(&mut text)));
+                    }
+
+                    let tag = tag.trim();
+                    let (sigil, name) = match tag.chars().next() {
+                        Some(sigil @ ('?' | '!' | '/')) => (Some(sigil), tag[1..].trim()),
+                        _ => (None, tag),
+                    };
+                    let path = parse_template_path(name, &schema)?;
+                    match sigil {
+                        Some('/') => {
+                            let Some((open_path, inverted, outer)) = open.pop() else {
+                                return Err(format!("'{{/{name}}}' closes no section"));
+                            };
+                            if open_path != path {
+                                return Err(format!(
+                                    "'{{/{name}}}' closes '{}'",
+                                    open_path.join(".")
+                                ));
+                            }
+                            let body = std::mem::replace(&mut nodes, outer);
+                            nodes.push(TemplateNode::Section {
+                                path,
+                                inverted,
+                                body,
+                            });
+                        }
+                        Some(sigil) => {
+                            open.push((path, sigil == '!', std::mem::take(&mut nodes)));
+                        }
+                        None => nodes.push(TemplateNode::Field(path)),
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if let Some((path, _, _)) = open.last() {
+            return Err(format!("section '{}' is never closed", path.join(".")));
+        }
+        if !text.is_empty() {
+            nodes.push(TemplateNode::Text(text));
+        }
+        Ok(Template { nodes })
+    }
+
+    fn render(&self, listener: &AggregatedListener) -> String {
+        let value = serde_json::to_value(listener).unwrap_or_default();
+        let mut out = String::new();
+        render_template_nodes(&self.nodes, &value, &mut out);
+        out
+    }
+}
+
+/// Protocol selection for --proto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProtoFilter {
+    Tcp,
+    Udp,
+    All,
+}
+
+impl ProtoFilter {
+    /// Protocols covered by this selection
+    fn protocols(self) -> &'static [Protocol] {
+        match self {
+            ProtoFilter::Tcp => &[Protocol::Tcp],
+            ProtoFilter::Udp => &[Protocol::Udp],
+            ProtoFilter::All => &[Protocol::Tcp, Protocol::Udp],
+        }
+    }
 }
 
 /// Parse and validate port number
@@ -43,7 +381,7 @@ fn parse_port(s: &str) -> Result<u16, String> {
 /// Custom error type for whichport operations
 #[derive(Error, Debug)]
 enum WhichportError {
-    #[error("no ports specified and --all not provided")]
+    #[error("no ports specified and neither --all nor --unix provided")]
     NoPorts,
 
     #[error("failed to run {command}: {details}")]
@@ -54,22 +392,65 @@ enum WhichportError {
 
     #[error("all collection methods failed: {0}")]
     AllMethodsFailed(String),
+
+    #[error("failed to read {path}: {details}")]
+    ReadFailed { path: String, details: String },
+
+    #[error("netlink sock_diag failed: {0}")]
+    NetlinkFailed(String),
+
+    #[error("network namespace error: {0}")]
+    NetnsFailed(String),
+
+    #[error("invalid rules file {path}: {details}")]
+    RulesFailed { path: String, details: String },
+
+    #[error("invalid template: {0}")]
+    TemplateFailed(String),
+}
+
+/// Transport protocol of a listening socket
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+    /// Unix domain stream/seqpacket socket; port is 0 and endpoints are paths
+    Unix,
+}
+
+impl Protocol {
+    fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Unix => "unix",
+        }
+    }
 }
 
 /// Individual listener entry
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 struct Listener {
     port: u16,
+    protocol: Protocol,
     pid: Option<u32>,
     command: String,
     user: String,
     endpoint: String,
+    /// Network namespace identifier (--netns only)
+    netns: Option<String>,
 }
 
 /// Aggregated listener with multiple endpoints
 #[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct AggregatedListener {
     port: u16,
+    protocol: Protocol,
     pid: Option<u32>,
     command: String,
     user: String,
@@ -79,6 +460,179 @@ struct AggregatedListener {
     endpoints: Vec<String>,
     /// Inferred role information
     role: Role,
+    /// Service name registered for this port/protocol in /etc/services
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<String>,
+    /// Established connections grouped by remote address (--connections only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connections: Option<Vec<ConnectionGroup>>,
+    /// Network namespace identifier (--netns only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    netns: Option<String>,
+    /// Container the owning process runs in, from /proc/<pid>/cgroup (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    container: Option<ContainerInfo>,
+    /// systemd service unit the owning process belongs to (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    /// systemd socket unit holding this listener for socket activation (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket_activation: Option<SocketActivation>,
+    /// Protocol detected by --probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    probe: Option<ProbeResult>,
+    /// HTTP response to `GET /` (--http only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http: Option<HttpFingerprint>,
+    /// Certificate served by the listener (--tls only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls: Option<TlsCertificate>,
+    /// Full command line of the owning process
+    cmdline: Option<String>,
+    /// Path of the owning process's executable
+    exe: Option<String>,
+    /// Working directory of the owning process
+    cwd: Option<String>,
+}
+
+/// Established TCP connection with its local owner, if known
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Connection {
+    local: SocketAddr,
+    remote: SocketAddr,
+    pid: Option<u32>,
+    command: Option<String>,
+}
+
+/// Connections to a listener from one remote address
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionGroup {
+    address: String,
+    count: usize,
+    peers: Vec<ConnectionPeer>,
+}
+
+/// Remote end of a connection; pid/command are set when the peer is a local process
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionPeer {
+    endpoint: String,
+    pid: Option<u32>,
+    command: Option<String>,
+}
+
+/// Container attribution parsed from a cgroup path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerInfo {
+    /// Runtime that created the container: docker, containerd, podman, cri-o or kubernetes
+    runtime: &'static str,
+    /// Full container ID (64 hex characters)
+    id: String,
+    /// Kubernetes pod UID for kubepods cgroups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pod_uid: Option<String>,
+}
+
+/// Protocol identified by actively probing a listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ProbeResult {
+    /// http, tls, redis, postgresql, mysql or ssh
+    protocol: &'static str,
+    /// Server banner, e.g. the HTTP `Server` header or the SSH version line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    banner: Option<String>,
+}
+
+/// Summary of an HTTP listener's response to `GET /`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpFingerprint {
+    status: u16,
+    server: Option<String>,
+    powered_by: Option<String>,
+    title: Option<String>,
+    /// Same-host redirect that was followed from `/`, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect: Option<String>,
+}
+
+/// Parsed HTTP response used for fingerprinting
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HttpResponse {
+    status: u16,
+    /// Header names are lowercased
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Part of an HTTP response inspected by a fingerprint rule
+enum HttpField {
+    Header(&'static str),
+    Title,
+    Body,
+}
+
+/// Role rule matched case-insensitively against an HTTP response
+struct HttpRule {
+    field: HttpField,
+    pattern: &'static str,
+    description: &'static str,
+    confidence: Confidence,
+}
+
+/// Leaf certificate served by a TLS listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TlsCertificate {
+    subject: String,
+    issuer: String,
+    /// Subject alternative names as printed by openssl, e.g. `DNS:localhost`
+    sans: Vec<String>,
+    /// Expiry in ISO 8601 (UTC)
+    not_after: String,
+    /// Whole days until expiry; negative once expired
+    days_until_expiry: i64,
+    expired: bool,
+    self_signed: bool,
+}
+
+/// Process details beyond the short command name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ProcessDetails {
+    cmdline: Option<String>,
+    exe: Option<String>,
+    cwd: Option<String>,
+}
+
+/// systemd socket unit and the service it activates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct SocketActivation {
+    socket: String,
+    service: String,
+}
+
+/// Address a systemd socket unit listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ListenAddress {
+    Port(Protocol, u16),
+    Path(String),
+}
+
+/// Parsed `.socket` unit file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SocketUnit {
+    name: String,
+    service: String,
+    listens: Vec<ListenAddress>,
 }
 
 /// Collection result with metadata
@@ -89,677 +643,6983 @@ struct CollectionResult {
     errors: Vec<String>,
 }
 
-/// Role inference result
-#[derive(Debug, Clone, Copy, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct Role {
-    description: &'static str,
-    confidence: &'static str,
+/// Socket entry decoded from kernel socket tables (/proc/net or sock_diag)
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct KernelSocket {
+    port: u16,
+    protocol: Protocol,
+    endpoint: String,
+    /// Unknown for Unix sockets, whose table has no owner column
+    uid: Option<u32>,
+    inode: u64,
 }
 
-/// JSON output structure for port query mode
-#[derive(Debug, Serialize)]
-struct PortQueryOutput {
-    mode: String,
-    source: String,
-    timestamp: u64,
-    errors: Vec<String>,
-    results: Vec<PortResult>,
+/// Network namespace discovered from /proc/<pid>/ns/net and /run/netns
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct NetNamespace {
+    inode: u64,
+    /// Name from /run/netns, if the namespace is bind-mounted there
+    name: Option<String>,
+    /// Lowest PID living in the namespace, used to read its /proc tables
+    pid: Option<u32>,
 }
 
-/// Individual port result
-#[derive(Debug, Serialize)]
-struct PortResult {
-    port: u16,
-    listening: bool,
-    listeners: Vec<AggregatedListener>,
+impl NetNamespace {
+    /// Identifier shown in output: the /run/netns name, or `net:[inode]`
+    fn id(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("net:[{}]", self.inode))
+    }
 }
 
-/// JSON output structure for all ports mode
-#[derive(Debug, Serialize)]
-struct AllPortsOutput {
-    mode: String,
-    source: String,
-    timestamp: u64,
-    errors: Vec<String>,
-    results: Vec<AggregatedListener>,
+/// Process that owns a socket, resolved through /proc/<pid>/fd
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct SocketOwner {
+    pid: u32,
+    command: String,
+    uid: Option<u32>,
 }
 
-/// Role inference rule
-struct RoleRule {
-    command_pattern: &'static str,
-    description: &'static str,
-    confidence: &'static str,
+/// How far a role inference can be trusted, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Confidence {
+    Low,
+    Medium,
+    High,
+    /// Verified by talking to the service (--probe)
+    Confirmed,
 }
 
-/// Common lsof arguments
-const LSOF_ARGS: &[&str] = &["-nP", "-iTCP", "-sTCP:LISTEN", "-FpcLnTu"];
-
-/// Role inference rules based on command name
-const COMMAND_RULES: &[RoleRule] = &[
-    RoleRule {
-        command_pattern: "postgres",
-        description: "PostgreSQL database",
-        confidence: "high",
-    },
-    RoleRule {
-        command_pattern: "redis",
-        description: "Redis cache or message broker",
-        confidence: "high",
-    },
-    RoleRule {
-        command_pattern: "nginx",
-        description: "Web server or reverse proxy",
-        confidence: "high",
-    },
-    RoleRule {
-        command_pattern: "docker",
-        description: "Container runtime backend",
-        confidence: "high",
-    },
-    RoleRule {
-        command_pattern: "ollama",
-        description: "Local LLM serving runtime",
-        confidence: "high",
-    },
-    RoleRule {
-        command_pattern: "rustrover",
-        description: "IDE or developer tooling service",
-        confidence: "medium",
-    },
-    RoleRule {
-        command_pattern: "jetbrains",
-        description: "IDE or developer tooling service",
-        confidence: "medium",
-    },
-    RoleRule {
-        command_pattern: "toolbox",
-        description: "IDE or developer tooling service",
-        confidence: "medium",
-    },
-    RoleRule {
-        command_pattern: "raycast",
-        description: "Productivity launcher local service",
-        confidence: "medium",
-    },
-    RoleRule {
-        command_pattern: "adobe",
-        description: "Adobe desktop background service",
-        confidence: "medium",
-    },
-    RoleRule {
-        command_pattern: "node",
-        description: "Node.js application server",
-        confidence: "medium",
-    },
-];
+impl Confidence {
+    fn as_str(self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+            Confidence::Confirmed => "confirmed",
+        }
+    }
+}
 
-/// Port-based role inference rules
-const PORT_RULES: &[(u16, &str, &str)] = &[
-    (22, "SSH service", "medium"),
-    (80, "HTTP web service", "medium"),
-    (443, "HTTPS web service", "medium"),
-    (3306, "MySQL database", "medium"),
-    (5432, "PostgreSQL database", "medium"),
-    (6379, "Redis cache or message broker", "medium"),
-];
+/// Kind of signal that produced a role candidate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EvidenceKind {
+    CommandRule,
+    CmdlineRule,
+    PortRule,
+    UnixPathRule,
+    SocketUnit,
+    Http,
+    Probe,
+    UserRule,
+    ServiceRegistry,
+    /// Nothing matched
+    Fallback,
+}
 
-fn main() {
-    if let Err(err) = run() {
-        eprintln!("error: {err}");
-        std::process::exit(1);
+impl EvidenceKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EvidenceKind::CommandRule => "command_rule",
+            EvidenceKind::CmdlineRule => "cmdline_rule",
+            EvidenceKind::PortRule => "port_rule",
+            EvidenceKind::UnixPathRule => "unix_path_rule",
+            EvidenceKind::SocketUnit => "socket_unit",
+            EvidenceKind::Http => "http",
+            EvidenceKind::Probe => "probe",
+            EvidenceKind::UserRule => "user_rule",
+            EvidenceKind::ServiceRegistry => "service_registry",
+            EvidenceKind::Fallback => "fallback",
+        }
     }
 }
 
-fn run() -> Result<(), WhichportError> {
-    let cli = Cli::parse();
+/// Signal behind a role candidate and the pattern, port or value it matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Evidence {
+    kind: EvidenceKind,
+    matched: String,
+}
 
-    // Validate that we have either ports or --all
-    if !cli.all && cli.ports.is_empty() {
-        return Err(WhichportError::NoPorts);
+impl Evidence {
+    fn new(kind: EvidenceKind, matched: impl Into<String>) -> Self {
+        Evidence {
+            kind,
+            matched: matched.into(),
+        }
     }
+}
 
-    let collected = collect_listeners()?;
-    let timestamp = unix_timestamp();
+/// A role that was considered but not chosen
+#[derive(Debug, Clone, Serialize)]
+struct RoleCandidate {
+    description: Cow<'static, str>,
+    confidence: Confidence,
+    evidence: Evidence,
+}
 
-    if cli.all {
-        if cli.json {
-            print_all_json(
-                &collected.listeners,
-                collected.source,
-                timestamp,
-                &collected.errors,
-            );
-        } else {
-            print_all_text(
-                &collected.listeners,
-                collected.source,
-                timestamp,
-                &collected.errors,
-                cli.verbose,
-            );
+/// Role inference result; descriptions from user rules are owned
+///
+/// `alternatives` holds the other candidates, ranked by confidence.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Role {
+    description: Cow<'static, str>,
+    confidence: Confidence,
+    evidence: Evidence,
+    alternatives: Vec<RoleCandidate>,
+}
+
+impl Role {
+    fn new(
+        description: impl Into<Cow<'static, str>>,
+        confidence: Confidence,
+        evidence: Evidence,
+    ) -> Self {
+        Role {
+            description: description.into(),
+            confidence,
+            evidence,
+            alternatives: Vec::new(),
         }
-        return Ok(());
     }
 
-    if cli.json {
-        print_ports_json(
-            &collected.listeners,
-            &cli.ports,
-            collected.source,
-            timestamp,
-            &collected.errors,
-        );
-    } else {
-        print_ports_text(
-            &collected.listeners,
-            &cli.ports,
-            collected.source,
-            timestamp,
-            &collected.errors,
-            cli.verbose,
-        );
+    fn unknown() -> Self {
+        Role::new(
+            UNKNOWN_ROLE,
+            Confidence::Medium,
+            Evidence::new(EvidenceKind::Fallback, ""),
+        )
     }
 
-    Ok(())
-}
-
-/// Collect listening ports using platform-appropriate methods
-fn collect_listeners() -> Result<CollectionResult, WhichportError> {
-    #[cfg(target_os = "linux")]
-    {
-        let mut errors = Vec::new();
+    fn candidate(&self) -> RoleCandidate {
+        RoleCandidate {
+            description: self.description.clone(),
+            confidence: self.confidence,
+            evidence: self.evidence.clone(),
+        }
+    }
 
-        // Try ss first on Linux
-        match collect_listeners_from_ss() {
-            Ok(listeners) => {
-                return Ok(CollectionResult {
-                    listeners,
-                    source: "ss",
-                    errors,
-                });
-            }
-            Err(err) => errors.push(err.to_string()),
+    /// Promote `other` if it is at least as confident as the current role, otherwise keep
+    /// it (and its alternatives) as alternatives
+    fn consider(&mut self, other: Role) {
+        if other.confidence >= self.confidence || self.description == UNKNOWN_ROLE {
+            self.supersede(other);
+            return;
         }
-
-        // Fallback to lsof
-        match collect_listeners_from_lsof() {
-            Ok(listeners) => {
-                return Ok(CollectionResult {
-                    listeners,
-                    source: "lsof",
-                    errors,
-                });
-            }
-            Err(err) => errors.push(err.to_string()),
+        self.add_alternative(other.candidate());
+        for candidate in other.alternatives {
+            self.add_alternative(candidate);
         }
+    }
 
-        Err(WhichportError::AllMethodsFailed(errors.join(" | ")))
+    /// Make `other` the chosen role regardless of confidence; the current role and all
+    /// alternatives are kept as alternatives. Reserved for user rules and confirmed probes.
+    fn supersede(&mut self, other: Role) {
+        let previous = std::mem::replace(self, other);
+        if previous.description != UNKNOWN_ROLE {
+            self.add_alternative(previous.candidate());
+        }
+        for candidate in previous.alternatives {
+            self.add_alternative(candidate);
+        }
     }
 
-    #[cfg(not(target_os = "linux"))]
-    {
-        let listeners = collect_listeners_from_lsof()?;
-        Ok(CollectionResult {
-            listeners,
-            source: "lsof",
-            errors: Vec::new(),
-        })
+    /// Record a candidate that did not win; duplicates of known descriptions are dropped
+    fn add_alternative(&mut self, candidate: RoleCandidate) {
+        let known = candidate.description == UNKNOWN_ROLE
+            || candidate.description == self.description
+            || self
+                .alternatives
+                .iter()
+                .any(|alternative| alternative.description == candidate.description);
+        if known {
+            return;
+        }
+        // Insert after every candidate at least as confident, keeping discovery order on ties
+        let index = self
+            .alternatives
+            .iter()
+            .position(|alternative| alternative.confidence < candidate.confidence)
+            .unwrap_or(self.alternatives.len());
+        self.alternatives.insert(index, candidate);
     }
 }
 
-/// Collect listeners using lsof command
-fn collect_listeners_from_lsof() -> Result<Vec<Listener>, WhichportError> {
-    let output = Command::new("lsof")
-        .args(LSOF_ARGS)
-        .output()
-        .map_err(|e| WhichportError::CommandFailed {
-            command: "lsof".to_string(),
-            details: e.to_string(),
-        })?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(WhichportError::CommandError {
-            command: "lsof".to_string(),
-            stderr: stderr.trim().to_string(),
-        });
-    }
+/// Rules file as written by the user: `[[rule]]` tables in TOML or a `rules` array in JSON
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRulesFile {
+    #[serde(default, rename = "rule", alias = "rules")]
+    rules: Vec<RawUserRule>,
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    Ok(parse_lsof_output(&stdout))
+/// One user rule before validation; every given condition must match
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawUserRule {
+    command: Option<String>,
+    #[serde(default)]
+    command_match: MatchMode,
+    #[serde(default)]
+    priority: i32,
+    port: Option<u16>,
+    port_range: Option<[u16; 2]>,
+    cmdline: Option<String>,
+    user: Option<String>,
+    description: String,
+    confidence: Option<String>,
 }
 
-/// Collect listeners using ss command (Linux only)
-#[cfg(target_os = "linux")]
-fn collect_listeners_from_ss() -> Result<Vec<Listener>, WhichportError> {
-    let output = Command::new("ss")
-        .args(["-lntpH"])
-        .output()
-        .map_err(|e| WhichportError::CommandFailed {
-            command: "ss".to_string(),
-            details: e.to_string(),
-        })?;
+/// Validated user rule, checked before the built-in tables
+#[derive(Debug)]
+struct UserRule {
+    /// Pattern for the lowercased command name
+    command: Option<TextMatcher>,
+    priority: i32,
+    port: Option<u16>,
+    port_range: Option<(u16, u16)>,
+    cmdline: Option<Regex>,
+    user: Option<String>,
+    description: String,
+    confidence: Confidence,
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(WhichportError::CommandError {
-            command: "ss".to_string(),
-            stderr: stderr.trim().to_string(),
-        });
+impl UserRule {
+    fn matches(&self, listener: &AggregatedListener) -> bool {
+        let is_port = listener.protocol != Protocol::Unix;
+        self.command
+            .as_ref()
+            .is_none_or(|c| c.is_match(&listener.command.to_ascii_lowercase()))
+            && self.port.is_none_or(|p| is_port && listener.port == p)
+            && self
+                .port_range
+                .is_none_or(|(lo, hi)| is_port && (lo..=hi).contains(&listener.port))
+            && self
+                .cmdline
+                .as_ref()
+                .is_none_or(|re| listener.cmdline.as_deref().is_some_and(|c| re.is_match(c)))
+            && self.user.as_ref().is_none_or(|u| listener.user == *u)
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    Ok(parse_ss_output(&stdout))
+    /// Rank among matching user rules: explicit priority, then number of conditions,
+    /// then how narrowly the command pattern matches
+    fn rank(&self) -> (i32, usize, u8) {
+        let conditions = [
+            self.command.is_some(),
+            self.port.is_some(),
+            self.port_range.is_some(),
+            self.cmdline.is_some(),
+            self.user.is_some(),
+        ];
+        let command_specificity = self.command.as_ref().map_or(0, |c| c.mode.specificity());
+        (
+            self.priority,
+            conditions.iter().filter(|&&set| set).count(),
+            command_specificity,
+        )
+    }
 }
 
-/// Parse lsof -F output format
-fn parse_lsof_output(raw: &str) -> Vec<Listener> {
-    let mut current_pid: Option<u32> = None;
-    let mut current_command: Option<String> = None;
-    let mut current_user: Option<String> = None;
+/// Version of the JSON output shape (--json, ndjson); bump on any breaking change
+const SCHEMA_VERSION: u32 = 1;
 
-    let mut out = Vec::new();
-    let mut dedup = HashSet::new();
+/// JSON Schema describing --json output, printed by --print-schema
+const OUTPUT_SCHEMA: &str = include_str!("../schema/output.schema.json");
 
-    for line in raw.lines() {
-        if line.is_empty() {
-            continue;
-        }
+/// Query mode reported in JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum QueryMode {
+    Ports,
+    All,
+    Unix,
+}
 
-        let (tag, value) = line.split_at(1);
-        match tag {
-            "p" => current_pid = value.parse::<u32>().ok(),
-            "c" => current_command = Some(value.to_string()),
-            "L" => current_user = Some(value.to_string()),
-            "u" if current_user.is_none() => current_user = Some(value.to_string()),
-            "n" => {
-                let port = match parse_port_from_endpoint(value) {
-                    Some(port) => port,
-                    None => continue,
-                };
+/// --json output envelope, shared by every query mode
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonOutput<'a> {
+    schema_version: u32,
+    mode: QueryMode,
+    source: &'a str,
+    timestamp: u64,
+    errors: &'a [String],
+    /// Queried ports in query order; empty unless mode is "ports"
+    ports: Vec<PortStatus>,
+    /// Listeners; in ports mode, in query order
+    listeners: Vec<&'a AggregatedListener>,
+}
 
-                if let (Some(command), Some(user)) =
-                    (current_command.as_ref(), current_user.as_ref())
-                {
-                    let record = Listener {
-                        port,
-                        pid: current_pid,
-                        command: command.clone(),
-                        user: user.clone(),
-                        endpoint: value.to_string(),
-                    };
+/// Whether a queried port has a listener
+#[derive(Debug, Serialize)]
+struct PortStatus {
+    port: u16,
+    listening: bool,
+}
 
-                    if dedup.insert(record.clone()) {
-                        out.push(record);
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
+/// One line of --format ndjson output, tagged by `type`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NdjsonRecord<'a> {
+    /// Leading record with the same header fields as --json
+    Meta {
+        #[serde(rename = "schemaVersion")]
+        schema_version: u32,
+        mode: QueryMode,
+        source: &'a str,
+        timestamp: u64,
+        errors: &'a [String],
+    },
+    Listener(&'a AggregatedListener),
+    /// Queried port without a listener (port mode only)
+    NotListening {
+        port: u16,
+    },
+}
 
-    out.sort_by_key(|l| (l.port, l.pid.unwrap_or(0)));
-    out
+/// Role inference rule
+///
+/// When several rules match, the most specific one wins (see `MatchMode::specificity`).
+struct RoleRule {
+    command_pattern: &'static str,
+    match_mode: MatchMode,
+    description: &'static str,
+    confidence: Confidence,
+    /// The service is known not to speak HTTP, so --http sends it no request
+    non_http: bool,
 }
 
-/// Parse ss output format (Linux)
-#[cfg(any(target_os = "linux", test))]
-fn parse_ss_output(raw: &str) -> Vec<Listener> {
-    let mut out = Vec::new();
-    let mut dedup = HashSet::new();
+impl RoleRule {
+    /// Rank used to pick between matching rules; ties keep table order
+    fn specificity(&self) -> (u8, usize) {
+        (self.match_mode.specificity(), self.command_pattern.len())
+    }
 
-    for line in raw.lines() {
-        if line.trim().is_empty() {
-            continue;
-        }
+    fn role(&self, kind: EvidenceKind) -> Role {
+        Role::new(
+            self.description,
+            self.confidence,
+            Evidence::new(kind, self.command_pattern),
+        )
+    }
+}
 
-        let tokens: Vec<&str> = line.split_whitespace().collect();
-        if tokens.len() < 4 {
-            continue;
-        }
+/// How a rule pattern is compared against a name, path or command line
+///
+/// Exact, prefix and glob patterns are anchored at the start (exact and glob also at the
+/// end); regex patterns are unanchored unless they use `^`/`$` themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MatchMode {
+    #[default]
+    Contains,
+    Exact,
+    Prefix,
+    Glob,
+    Regex,
+}
 
-        let endpoint = tokens[3];
-        let port = match parse_port_from_endpoint(endpoint) {
-            Some(port) => port,
-            None => continue,
-        };
+impl MatchMode {
+    /// Higher values describe narrower matches
+    fn specificity(self) -> u8 {
+        match self {
+            MatchMode::Exact => 4,
+            MatchMode::Prefix => 3,
+            MatchMode::Glob | MatchMode::Regex => 2,
+            MatchMode::Contains => 1,
+        }
+    }
+}
 
-        let proc_blob = if tokens.len() > 5 {
-            tokens[5..].join(" ")
-        } else {
-            String::new()
-        };
+/// Compiled text pattern for user rules; globs are translated to anchored regexes
+#[derive(Debug)]
+struct TextMatcher {
+    mode: MatchMode,
+    pattern: String,
+    regex: Option<Regex>,
+}
 
-        let (pid, command) = parse_ss_process_info(&proc_blob);
-        let record = Listener {
-            port,
-            pid,
-            command,
-            user: "-".to_string(),
-            endpoint: endpoint.to_string(),
+impl TextMatcher {
+    fn new(mode: MatchMode, pattern: &str) -> Result<Self, regex::Error> {
+        let regex = match mode {
+            MatchMode::Glob => Some(Regex::new(&glob_to_regex(pattern))?),
+            MatchMode::Regex => Some(Regex::new(pattern)?),
+            _ => None,
         };
+        Ok(TextMatcher {
+            mode,
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
 
-        if dedup.insert(record.clone()) {
-            out.push(record);
+    fn is_match(&self, text: &str) -> bool {
+        match (&self.regex, self.mode) {
+            (Some(regex), _) => regex.is_match(text),
+            (None, MatchMode::Exact) => text == self.pattern,
+            (None, MatchMode::Prefix) => text.starts_with(&self.pattern),
+            (None, _) => text.contains(&self.pattern),
         }
     }
+}
 
-    out.sort_by_key(|l| (l.port, l.pid.unwrap_or(0)));
-    out
+/// Translate a glob (`*`, `?`) into an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
 }
 
-/// Parse process information from ss output
-#[cfg(any(target_os = "linux", test))]
-fn parse_ss_process_info(raw: &str) -> (Option<u32>, String) {
-    let mut command = "unknown".to_string();
-    let mut pid = None;
+/// Built-in rule table; patterns are compiled once, on first use
+struct RuleTable {
+    rules: &'static [RoleRule],
+    matchers: OnceLock<Vec<TextMatcher>>,
+}
 
-    // Extract command name from quoted string
-    if let Some(start) = raw.find('"') {
-        let remain = &raw[start + 1..];
-        if let Some(end) = remain.find('"') {
-            command = remain[..end].to_string();
+impl RuleTable {
+    const fn new(rules: &'static [RoleRule]) -> Self {
+        RuleTable {
+            rules,
+            matchers: OnceLock::new(),
         }
     }
 
-    // Extract PID from pid= pattern
-    if let Some(idx) = raw.find("pid=") {
-        let digits: String = raw[idx + 4..]
-            .chars()
-            .take_while(|c| c.is_ascii_digit())
-            .collect();
-        pid = digits.parse::<u32>().ok();
+    fn compile(&self) -> Result<Vec<TextMatcher>, regex::Error> {
+        self.rules
+            .iter()
+            .map(|rule| TextMatcher::new(rule.match_mode, rule.command_pattern))
+            .collect()
     }
 
-    (pid, command)
+    /// Every built-in pattern is checked by a test, so compiling cannot fail at runtime
+    fn matchers(&self) -> &[TextMatcher] {
+        self.matchers
+            .get_or_init(|| self.compile().expect("built-in rule patterns are valid"))
+    }
 }
 
-/// Extract port number from endpoint string
-fn parse_port_from_endpoint(endpoint: &str) -> Option<u16> {
-    if let Some(idx) = endpoint.rfind(':') {
-        let port_str = &endpoint[idx + 1..];
-        return port_str.parse::<u16>().ok();
-    }
-    None
+/// Pick the most specific matching rule; on ties the earlier rule wins
+fn most_specific_rule(table: &'static RuleTable, text: &str) -> Option<&'static RoleRule> {
+    matching_rules(table, text).into_iter().next()
 }
 
-/// Infer the role of a service based on port and command name
-fn infer_role(port: u16, command: &str) -> Role {
-    let cmd = command.to_ascii_lowercase();
+/// All rules matching `text`, most specific first; ties keep table order
+fn matching_rules(table: &'static RuleTable, text: &str) -> Vec<&'static RoleRule> {
+    let mut matched: Vec<&RoleRule> = table
+        .rules
+        .iter()
+        .zip(table.matchers())
+        .filter(|(_, matcher)| matcher.is_match(text))
+        .map(|(rule, _)| rule)
+        .collect();
+    matched.sort_by_key(|rule| std::cmp::Reverse(rule.specificity()));
+    matched
+}
 
-    // Check command-based rules first (higher priority)
-    for rule in COMMAND_RULES {
-        if cmd.contains(rule.command_pattern) {
-            return Role {
-                description: rule.description,
-                confidence: rule.confidence,
-            };
-        }
-    }
+/// Cgroup scope prefixes that identify a container runtime, e.g. `docker-<id>.scope`
+const CGROUP_SCOPE_RUNTIMES: &[(&str, &str)] = &[
+    ("docker-", "docker"),
+    ("cri-containerd-", "containerd"),
+    ("nerdctl-", "containerd"),
+    ("libpod-", "podman"),
+    ("crio-", "cri-o"),
+];
 
-    // Check port-based rules
-    for &(rule_port, description, confidence) in PORT_RULES {
-        if port == rule_port {
-            return Role {
-                description,
-                confidence,
-            };
-        }
-    }
+/// Cgroup v1 parent directories that hold bare container IDs, e.g. `/docker/<id>`
+const CGROUP_PARENT_RUNTIMES: &[(&str, &str)] =
+    &[("docker", "docker"), ("libpod_parent", "podman")];
 
-    // Default fallback
-    Role {
-        description: "Unknown application service",
-        confidence: "medium",
-    }
-}
+/// Connect and read timeouts for --probe; each probe opens at most five short connections
+const PROBE_CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+const PROBE_READ_TIMEOUT: Duration = Duration::from_millis(500);
 
-/// Aggregate listeners by (port, pid, command, user) and merge endpoints
-fn aggregate_listeners(listeners: &[Listener]) -> Vec<AggregatedListener> {
-    let mut grouped: BTreeMap<(u16, Option<u32>, String, String), BTreeSet<String>> =
-        BTreeMap::new();
+/// Largest reply read from a probed service
+const PROBE_MAX_REPLY: usize = 2048;
 
-    for listener in listeners {
-        grouped
-            .entry((
-                listener.port,
-                listener.pid,
-                listener.command.clone(),
-                listener.user.clone(),
-            ))
-            .or_default()
-            .insert(listener.endpoint.clone());
+/// Most listeners contacted at once by --probe, --http and --tls
+const PROBE_WORKERS: usize = 8;
+
+/// Request sent by the HTTP probe
+const HTTP_HEAD_REQUEST: &[u8] =
+    b"HEAD / HTTP/1.0\r\nHost: localhost\r\nUser-Agent: whichport\r\n\r\n";
+
+/// PostgreSQL SSLRequest: length 8, request code 80877103
+const PG_SSL_REQUEST: &[u8] = &[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+/// Redis inline PING command
+const REDIS_PING: &[u8] = b"PING\r\n";
+
+/// Largest HTTP response read by --http; titles live near the top of the page
+const HTTP_MAX_RESPONSE: usize = 64 * 1024;
+
+/// Overall limit for one --http request; streaming responses are cut off here
+const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long --http waits for an unsolicited greeting before sending its request
+const HTTP_GREETING_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// HTTP fingerprint rules, most specific first; patterns are lowercase substrings
+const HTTP_RULES: &[HttpRule] = &[
+    HttpRule {
+        field: HttpField::Body,
+        pattern: "/@vite/client",
+        description: "Vite development server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Header("x-powered-by"),
+        pattern: "next.js",
+        description: "Next.js application server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Header("x-powered-by"),
+        pattern: "nuxt",
+        description: "Nuxt application server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Header("x-jenkins"),
+        pattern: "",
+        description: "Jenkins CI server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Title,
+        pattern: "grafana",
+        description: "Grafana dashboard",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Title,
+        pattern: "prometheus",
+        description: "Prometheus monitoring server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Header("kbn-name"),
+        pattern: "",
+        description: "Kibana dashboard",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Body,
+        pattern: "you know, for search",
+        description: "Elasticsearch search engine",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Title,
+        pattern: "jupyter",
+        description: "Jupyter notebook server",
+        confidence: Confidence::High,
+    },
+    HttpRule {
+        field: HttpField::Body,
+        pattern: "webpack-dev-server",
+        description: "webpack dev server",
+        confidence: Confidence::Medium,
+    },
+    HttpRule {
+        field: HttpField::Title,
+        pattern: "react app",
+        description: "React development server",
+        confidence: Confidence::Medium,
+    },
+    HttpRule {
+        field: HttpField::Header("x-powered-by"),
+        pattern: "express",
+        description: "Express.js application server",
+        confidence: Confidence::Medium,
+    },
+    HttpRule {
+        field: HttpField::Header("x-powered-by"),
+        pattern: "php",
+        description: "PHP web application",
+        confidence: Confidence::Medium,
+    },
+];
+
+/// Upper bound for each openssl invocation made by --tls
+const TLS_COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Default rules file location under the user's config directory
+const USER_RULES_FILE: &str = "whichport/rules.toml";
+
+/// Kernel socket tables read by the /proc collector, relative to a net directory
+#[cfg(target_os = "linux")]
+const PROC_NET_TCP_TABLES: &[&str] = &["tcp", "tcp6"];
+#[cfg(target_os = "linux")]
+const PROC_NET_UDP_TABLES: &[&str] = &["udp", "udp6"];
+
+/// Directories searched for `.socket` units, highest precedence first
+#[cfg(target_os = "linux")]
+const SYSTEMD_UNIT_DIRS: &[&str] = &["/etc/systemd/system", "/usr/lib/systemd/system"];
+
+/// Directory holding named network namespaces (`ip netns add`)
+#[cfg(target_os = "linux")]
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// Unix socket table read by the --unix collector
+#[cfg(target_os = "linux")]
+const PROC_NET_UNIX_PATH: &str = "/proc/net/unix";
+
+/// __SO_ACCEPTCON flag marking listening sockets in /proc/net/unix
+#[cfg(any(target_os = "linux", test))]
+const UNIX_FLAG_ACCEPTCON: u32 = 0x10000;
+/// SOCK_STREAM and SOCK_SEQPACKET in the `Type` column of /proc/net/unix
+#[cfg(any(target_os = "linux", test))]
+const UNIX_LISTEN_TYPES: &[&str] = &["0001", "0005"];
+
+/// TCP_LISTEN state as printed in the `st` column of /proc/net/tcp
+#[cfg(any(target_os = "linux", test))]
+const PROC_TCP_LISTEN: &str = "0A";
+/// TCP_CLOSE state, which /proc/net/udp uses for bound, unconnected sockets
+#[cfg(any(target_os = "linux", test))]
+const PROC_UDP_UNCONN: &str = "07";
+
+/// Netlink message types, flags and struct sizes used by the sock_diag collector
+#[cfg(any(target_os = "linux", test))]
+const NLMSG_HEADER_LEN: usize = 16;
+#[cfg(any(target_os = "linux", test))]
+const NLMSG_ERROR: u16 = 2;
+#[cfg(any(target_os = "linux", test))]
+const NLMSG_DONE: u16 = 3;
+#[cfg(any(target_os = "linux", test))]
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+#[cfg(any(target_os = "linux", test))]
+const NLM_F_REQUEST: u16 = 0x1;
+#[cfg(any(target_os = "linux", test))]
+const NLM_F_DUMP: u16 = 0x300;
+#[cfg(any(target_os = "linux", test))]
+const INET_DIAG_REQ_V2_LEN: usize = 56;
+#[cfg(any(target_os = "linux", test))]
+const INET_DIAG_MSG_LEN: usize = 72;
+#[cfg(any(target_os = "linux", test))]
+const AF_INET_FAMILY: i32 = 2;
+#[cfg(any(target_os = "linux", test))]
+const AF_INET6_FAMILY: i32 = 10;
+#[cfg(target_os = "linux")]
+const TCP_STATE_LISTEN: u32 = 10;
+#[cfg(target_os = "linux")]
+const TCP_STATE_CLOSE: u32 = 7;
+
+/// Common lsof arguments; protocol selectors are appended per query
+const LSOF_ARGS: &[&str] = &["-nP", "-FpcLnTuP"];
+
+/// lsof arguments for established TCP connections
+#[cfg(not(target_os = "linux"))]
+const LSOF_ESTABLISHED_ARGS: &[&str] = &["-nP", "-iTCP", "-sTCP:ESTABLISHED", "-FpcnP"];
+
+/// TCP_ESTABLISHED state as printed in the `st` column of /proc/net/tcp
+#[cfg(any(target_os = "linux", test))]
+const PROC_TCP_ESTABLISHED: &str = "01";
+
+/// lsof arguments for Unix domain sockets
+#[cfg(not(target_os = "linux"))]
+const LSOF_UNIX_ARGS: &[&str] = &["-nP", "-U", "-FpcLnu"];
+
+/// lsof arguments for the working directory and executable of selected PIDs
+#[cfg(not(target_os = "linux"))]
+const LSOF_PROCESS_PATH_ARGS: &[&str] = &["-a", "-d", "cwd,txt", "-Ffn"];
+
+/// Role inference rules based on command name
+static COMMAND_RULES: RuleTable = RuleTable::new(&[
+    RoleRule {
+        command_pattern: "postgres",
+        match_mode: MatchMode::Prefix,
+        description: "PostgreSQL database",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "redis",
+        match_mode: MatchMode::Prefix,
+        description: "Redis cache or message broker",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "nginx",
+        match_mode: MatchMode::Prefix,
+        description: "Web server or reverse proxy",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "docker-proxy",
+        match_mode: MatchMode::Exact,
+        description: "Docker published port proxy",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "docker",
+        match_mode: MatchMode::Prefix,
+        description: "Container runtime backend",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    // Docker Desktop on macOS
+    RoleRule {
+        command_pattern: "com.docker",
+        match_mode: MatchMode::Prefix,
+        description: "Container runtime backend",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "ollama",
+        match_mode: MatchMode::Prefix,
+        description: "Local LLM serving runtime",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "rustrover",
+        match_mode: MatchMode::Contains,
+        description: "IDE or developer tooling service",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "jetbrains",
+        match_mode: MatchMode::Contains,
+        description: "IDE or developer tooling service",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "toolbox",
+        match_mode: MatchMode::Contains,
+        description: "IDE or developer tooling service",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "raycast",
+        match_mode: MatchMode::Prefix,
+        description: "Productivity launcher local service",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "adobe",
+        match_mode: MatchMode::Contains,
+        description: "Adobe desktop background service",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "node",
+        match_mode: MatchMode::Exact,
+        description: "Node.js application server",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+]);
+
+/// Role inference rules matched against the full command line and executable path
+///
+/// Checked before the command name rules, since interpreters and JVMs share one comm name.
+/// Longer patterns win, so `kafka.kafka` outranks the weaker `kafka` hint.
+static CMDLINE_RULES: RuleTable = RuleTable::new(&[
+    RoleRule {
+        command_pattern: "kafka.kafka",
+        match_mode: MatchMode::Contains,
+        description: "Apache Kafka broker",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "org.elasticsearch.bootstrap",
+        match_mode: MatchMode::Contains,
+        description: "Elasticsearch search engine",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "manage.py runserver",
+        match_mode: MatchMode::Contains,
+        description: "Django development server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "flask run",
+        match_mode: MatchMode::Contains,
+        description: "Flask development server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "uvicorn",
+        match_mode: MatchMode::Contains,
+        description: "ASGI application server (uvicorn)",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "gunicorn",
+        match_mode: MatchMode::Contains,
+        description: "WSGI application server (gunicorn)",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "webpack-dev-server",
+        match_mode: MatchMode::Contains,
+        description: "webpack dev server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "webpack serve",
+        match_mode: MatchMode::Contains,
+        description: "webpack dev server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "jupyter",
+        match_mode: MatchMode::Contains,
+        description: "Jupyter notebook server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "/.bin/vite",
+        match_mode: MatchMode::Contains,
+        description: "Vite development server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "vite/bin/vite",
+        match_mode: MatchMode::Contains,
+        description: "Vite development server",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    // Weaker hints: the name appears somewhere in a path or argument
+    RoleRule {
+        command_pattern: "kafka",
+        match_mode: MatchMode::Contains,
+        description: "Apache Kafka broker",
+        confidence: Confidence::Medium,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "elasticsearch",
+        match_mode: MatchMode::Contains,
+        description: "Elasticsearch search engine",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "django",
+        match_mode: MatchMode::Contains,
+        description: "Django application server",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "-m flask",
+        match_mode: MatchMode::Contains,
+        description: "Flask development server",
+        confidence: Confidence::Medium,
+        non_http: false,
+    },
+]);
+
+/// Role inference rules based on Unix socket path substrings
+static UNIX_PATH_RULES: RuleTable = RuleTable::new(&[
+    RoleRule {
+        command_pattern: ".s.pgsql",
+        match_mode: MatchMode::Contains,
+        description: "PostgreSQL database",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "mysql",
+        match_mode: MatchMode::Contains,
+        description: "MySQL database",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "redis",
+        match_mode: MatchMode::Contains,
+        description: "Redis cache or message broker",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "docker.sock",
+        match_mode: MatchMode::Contains,
+        description: "Docker engine API",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "containerd",
+        match_mode: MatchMode::Contains,
+        description: "containerd runtime API",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "podman",
+        match_mode: MatchMode::Contains,
+        description: "Podman API service",
+        confidence: Confidence::High,
+        non_http: false,
+    },
+    RoleRule {
+        command_pattern: "php-fpm",
+        match_mode: MatchMode::Contains,
+        description: "PHP FastCGI process manager",
+        confidence: Confidence::High,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "dbus",
+        match_mode: MatchMode::Contains,
+        description: "D-Bus message bus",
+        confidence: Confidence::Medium,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "systemd",
+        match_mode: MatchMode::Contains,
+        description: "systemd manager IPC",
+        confidence: Confidence::Medium,
+        non_http: true,
+    },
+    RoleRule {
+        command_pattern: "ssh",
+        match_mode: MatchMode::Contains,
+        description: "SSH agent or multiplexing socket",
+        confidence: Confidence::Medium,
+        non_http: true,
+    },
+]);
+
+/// Port-based role inference rules for UDP sockets
+const UDP_PORT_RULES: &[(u16, &str, Confidence)] = &[
+    (53, "DNS resolver or server", Confidence::Medium),
+    (67, "DHCP server", Confidence::Medium),
+    (68, "DHCP client", Confidence::Medium),
+    (123, "NTP time service", Confidence::Medium),
+    (161, "SNMP agent", Confidence::Medium),
+    (443, "QUIC / HTTP/3 service", Confidence::Medium),
+    (514, "Syslog receiver", Confidence::Medium),
+    (5353, "mDNS service discovery", Confidence::Medium),
+    (8125, "StatsD metrics collector", Confidence::Medium),
+];
+
+/// IANA service name and port number registry as installed by the OS
+const SERVICES_PATH: &str = "/etc/services";
+
+/// Role description used when no rule matches
+const UNKNOWN_ROLE: &str = "Unknown application service";
+
+/// Port-based role inference rules; the flag marks services known not to speak plain HTTP
+const PORT_RULES: &[(u16, &str, Confidence, bool)] = &[
+    (22, "SSH service", Confidence::Medium, true),
+    (80, "HTTP web service", Confidence::Medium, false),
+    (443, "HTTPS web service", Confidence::Medium, true),
+    (3306, "MySQL database", Confidence::Medium, true),
+    (5432, "PostgreSQL database", Confidence::Medium, true),
+    (
+        6379,
+        "Redis cache or message broker",
+        Confidence::Medium,
+        true,
+    ),
+];
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), WhichportError> {
+    let cli = Cli::parse();
+
+    if cli.print_schema {
+        print!("{OUTPUT_SCHEMA}");
+        return Ok(());
+    }
+
+    // Validate that we have either ports, --all or --unix
+    if !cli.all && !cli.unix && cli.ports.is_empty() {
+        return Err(WhichportError::NoPorts);
+    }
+
+    let user_rules = load_user_rules(cli.rules.as_deref())?;
+    let template = load_template(cli.template.as_deref(), cli.template_file.as_deref())?;
+
+    let proto = if cli.udp { ProtoFilter::Udp } else { cli.proto };
+    let collected = if cli.unix {
+        collect_unix_listeners()?
+    } else if let Some(selector) = &cli.netns {
+        collect_netns(selector, proto.protocols())?
+    } else {
+        collect_listeners(proto.protocols())?
+    };
+    let timestamp = unix_timestamp();
+    let mut errors = collected.errors;
+    let mut aggregated = aggregate_listeners(&collected.listeners);
+    attach_cgroup_info(&mut aggregated);
+    attach_socket_activation(&mut aggregated);
+    attach_process_details(&mut aggregated);
+    attach_published_port_containers(&mut aggregated);
+    refine_roles_from_cmdline(&mut aggregated);
+    apply_user_rules(&mut aggregated, &user_rules);
+
+    if cli.probe {
+        probe_unknown_listeners(&mut aggregated);
+    }
+    if cli.http {
+        fingerprint_http_listeners(&mut aggregated);
+    }
+
+    attach_service_names(&mut aggregated);
+    if cli.tls {
+        errors.extend(inspect_tls_listeners(&mut aggregated, timestamp));
+    }
+
+    if cli.connections {
+        match collect_connections() {
+            Ok((connections, warnings)) => {
+                errors.extend(warnings);
+                attach_connections(&mut aggregated, &connections);
+            }
+            // The listener view is still useful without connections
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    if let Some(template) = &template {
+        let ports = (!cli.all && !cli.unix).then_some(cli.ports.as_slice());
+        print_templated(&aggregated, ports, template);
+        return Ok(());
+    }
+
+    if cli.all || cli.unix {
+        let mode = if cli.unix {
+            QueryMode::Unix
+        } else {
+            QueryMode::All
+        };
+        if cli.json {
+            print_json(
+                &aggregated,
+                None,
+                mode,
+                collected.source,
+                timestamp,
+                &errors,
+            );
+        } else if cli.format == OutputFormat::Ndjson {
+            print_ndjson(
+                &aggregated,
+                None,
+                mode,
+                collected.source,
+                timestamp,
+                &errors,
+            );
+        } else if cli.format == OutputFormat::Text {
+            print_all_text(
+                &aggregated,
+                collected.source,
+                timestamp,
+                &errors,
+                cli.verbose,
+                cli.truncate,
+            );
+        } else {
+            if cli.format == OutputFormat::Table {
+                print_text_meta(collected.source, timestamp, &errors, cli.verbose);
+            }
+            print_rows(&aggregated, None, &cli);
+        }
+        return Ok(());
+    }
+
+    if cli.json {
+        print_json(
+            &aggregated,
+            Some(&cli.ports),
+            QueryMode::Ports,
+            collected.source,
+            timestamp,
+            &errors,
+        );
+    } else if cli.format == OutputFormat::Ndjson {
+        print_ndjson(
+            &aggregated,
+            Some(&cli.ports),
+            QueryMode::Ports,
+            collected.source,
+            timestamp,
+            &errors,
+        );
+    } else if cli.format == OutputFormat::Text {
+        print_ports_text(
+            &aggregated,
+            &cli.ports,
+            collected.source,
+            timestamp,
+            &errors,
+            cli.verbose,
+            cli.truncate,
+        );
+    } else {
+        if cli.format == OutputFormat::Table {
+            print_text_meta(collected.source, timestamp, &errors, cli.verbose);
+        }
+        print_rows(&aggregated, Some(&cli.ports), &cli);
+    }
+
+    Ok(())
+}
+
+/// Collect listening ports using platform-appropriate methods
+fn collect_listeners(protocols: &[Protocol]) -> Result<CollectionResult, WhichportError> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut errors = Vec::new();
+
+        // Ask the kernel directly first; avoids spawning a process per call
+        match collect_listeners_from_netlink(protocols) {
+            Ok((listeners, warnings)) => {
+                errors.extend(warnings);
+                return Ok(CollectionResult {
+                    listeners,
+                    source: "netlink",
+                    errors,
+                });
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        // Fall back to ss
+        match collect_listeners_from_ss(protocols) {
+            Ok(listeners) => {
+                return Ok(CollectionResult {
+                    listeners,
+                    source: "ss",
+                    errors,
+                });
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        // Fallback to lsof
+        match collect_listeners_from_lsof(protocols) {
+            Ok(listeners) => {
+                return Ok(CollectionResult {
+                    listeners,
+                    source: "lsof",
+                    errors,
+                });
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        // Last resort: read the kernel socket tables directly
+        match collect_listeners_from_proc(protocols) {
+            Ok((listeners, warnings)) => {
+                errors.extend(warnings);
+                return Ok(CollectionResult {
+                    listeners,
+                    source: "proc",
+                    errors,
+                });
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        Err(WhichportError::AllMethodsFailed(errors.join(" | ")))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let listeners = collect_listeners_from_lsof(protocols)?;
+        Ok(CollectionResult {
+            listeners,
+            source: "lsof",
+            errors: Vec::new(),
+        })
+    }
+}
+
+/// Collect listeners per network namespace; only Linux has network namespaces
+fn collect_netns(
+    selector: &NetnsSelector,
+    protocols: &[Protocol],
+) -> Result<CollectionResult, WhichportError> {
+    #[cfg(target_os = "linux")]
+    {
+        collect_netns_listeners(selector, protocols)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (selector, protocols);
+        Err(WhichportError::NetnsFailed(
+            "network namespaces are only supported on Linux".to_string(),
+        ))
+    }
+}
+
+/// Collect listening Unix domain sockets using platform-appropriate methods
+fn collect_unix_listeners() -> Result<CollectionResult, WhichportError> {
+    #[cfg(target_os = "linux")]
+    {
+        let raw = std::fs::read_to_string(PROC_NET_UNIX_PATH).map_err(|e| {
+            WhichportError::ReadFailed {
+                path: PROC_NET_UNIX_PATH.to_string(),
+                details: e.to_string(),
+            }
+        })?;
+        let sockets = parse_proc_net_unix(&raw);
+        let inodes: HashSet<u64> = sockets.iter().map(|s| s.inode).collect();
+        let (owners, errors) = resolve_socket_owners(&inodes);
+        let users = load_user_names();
+
+        Ok(CollectionResult {
+            listeners: kernel_sockets_to_listeners(&sockets, &owners, &users),
+            source: "proc",
+            errors,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let output = Command::new("lsof")
+            .args(LSOF_UNIX_ARGS)
+            .output()
+            .map_err(|e| WhichportError::CommandFailed {
+                command: "lsof".to_string(),
+                details: e.to_string(),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(WhichportError::CommandError {
+                command: "lsof".to_string(),
+                stderr: stderr.trim().to_string(),
+            });
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(CollectionResult {
+            listeners: parse_lsof_unix_output(&stdout),
+            source: "lsof",
+            errors: Vec::new(),
+        })
+    }
+}
+
+/// Collect established TCP connections using platform-appropriate methods
+///
+/// Returns the connections along with non-fatal warnings from owner resolution.
+fn collect_connections() -> Result<(Vec<Connection>, Vec<String>), WhichportError> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut established = Vec::new();
+        for table in PROC_NET_TCP_TABLES {
+            let path = format!("/proc/net/{table}");
+            match std::fs::read_to_string(&path) {
+                Ok(raw) => established.extend(parse_proc_net_established(&raw)),
+                // tcp6 is absent when IPv6 is disabled
+                Err(_) if table.ends_with('6') => {}
+                Err(e) => {
+                    return Err(WhichportError::ReadFailed {
+                        path,
+                        details: e.to_string(),
+                    })
+                }
+            }
+        }
+
+        let inodes: HashSet<u64> = established.iter().map(|(_, _, inode)| *inode).collect();
+        let (owners, warnings) = resolve_socket_owners(&inodes);
+        let connections = established
+            .into_iter()
+            .map(|(local, remote, inode)| {
+                let owner = owners.get(&inode);
+                Connection {
+                    local,
+                    remote,
+                    pid: owner.map(|o| o.pid),
+                    command: owner.map(|o| o.command.clone()),
+                }
+            })
+            .collect();
+        Ok((connections, warnings))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let output = Command::new("lsof")
+            .args(LSOF_ESTABLISHED_ARGS)
+            .output()
+            .map_err(|e| WhichportError::CommandFailed {
+                command: "lsof".to_string(),
+                details: e.to_string(),
+            })?;
+
+        // lsof exits non-zero when nothing matches, which simply means no connections
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok((parse_lsof_connections(&stdout), Vec::new()))
+    }
+}
+
+/// Parse ESTABLISHED entries from /proc/net/tcp{,6} as (local, remote, inode)
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_net_established(raw: &str) -> Vec<(SocketAddr, SocketAddr, u64)> {
+    let mut out = Vec::new();
+
+    for line in raw.lines().skip(1) {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 10 || tokens[3] != PROC_TCP_ESTABLISHED {
+            continue;
+        }
+
+        let (local, remote) = match (
+            decode_proc_address(tokens[1]),
+            decode_proc_address(tokens[2]),
+        ) {
+            (Some(local), Some(remote)) => (local, remote),
+            _ => continue,
+        };
+        let inode = tokens[9].parse::<u64>().unwrap_or(0);
+
+        out.push((
+            normalize_socket_addr(local),
+            normalize_socket_addr(remote),
+            inode,
+        ));
+    }
+
+    out
+}
+
+/// Parse lsof -F output for established connections (`n<local>-><remote>`)
+#[cfg(any(not(target_os = "linux"), test))]
+fn parse_lsof_connections(raw: &str) -> Vec<Connection> {
+    let mut current_pid: Option<u32> = None;
+    let mut current_command: Option<String> = None;
+    let mut out = Vec::new();
+
+    for line in raw.lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let (tag, value) = line.split_at(1);
+        match tag {
+            "p" => current_pid = value.parse::<u32>().ok(),
+            "c" => current_command = Some(value.to_string()),
+            "n" => {
+                let parsed = value.split_once("->").and_then(|(local, remote)| {
+                    Some((
+                        local.parse::<SocketAddr>().ok()?,
+                        remote.parse::<SocketAddr>().ok()?,
+                    ))
+                });
+                if let Some((local, remote)) = parsed {
+                    out.push(Connection {
+                        local: normalize_socket_addr(local),
+                        remote: normalize_socket_addr(remote),
+                        pid: current_pid,
+                        command: current_command.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out
+}
+
+/// Map IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) to plain IPv4
+///
+/// Dual-stack listeners see IPv4 peers as mapped addresses, while the peer's own
+/// socket reports plain IPv4; normalizing lets both ends be matched.
+fn normalize_socket_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Whether a connection's local address was accepted by a listener with these endpoints
+fn listener_accepts(endpoints: &[String], local: SocketAddr) -> bool {
+    endpoints.iter().any(|endpoint| {
+        if parse_port_from_endpoint(endpoint) != Some(local.port()) {
+            return false;
+        }
+        match endpoint.parse::<SocketAddr>() {
+            Ok(addr) => {
+                let addr = normalize_socket_addr(addr);
+                addr.ip().is_unspecified() || addr.ip() == local.ip()
+            }
+            // lsof prints wildcard binds as `*:port`; ss may add `%iface` scopes
+            Err(_) => endpoint.starts_with('*') || endpoint.contains('%'),
+        }
+    })
+}
+
+/// Attach container and systemd unit attribution from each process's cgroup
+fn attach_cgroup_info(aggregated: &mut [AggregatedListener]) {
+    #[cfg(target_os = "linux")]
+    {
+        let mut cache: HashMap<u32, Option<String>> = HashMap::new();
+        for listener in aggregated.iter_mut() {
+            let Some(pid) = listener.pid else {
+                continue;
+            };
+            let raw = cache
+                .entry(pid)
+                .or_insert_with(|| std::fs::read_to_string(format!("/proc/{pid}/cgroup")).ok());
+            if let Some(raw) = raw {
+                listener.container = parse_cgroup_container(raw);
+                listener.unit = parse_cgroup_unit(raw);
+            }
+        }
+    }
+
+    // macOS has no cgroups; containers there run inside a VM
+    #[cfg(not(target_os = "linux"))]
+    let _ = aggregated;
+}
+
+/// Attribute docker-proxy listeners to the container behind the published port
+///
+/// docker-proxy runs in the host's cgroup, so the container is found through its
+/// `-container-ip` argument: the network namespace holding that address belongs to it.
+fn attach_published_port_containers(aggregated: &mut [AggregatedListener]) {
+    #[cfg(target_os = "linux")]
+    {
+        let is_proxy = |listener: &AggregatedListener| {
+            listener.command == "docker-proxy" && listener.container.is_none()
+        };
+        if !aggregated.iter().any(is_proxy) {
+            return;
+        }
+
+        let namespaces = discover_namespaces();
+        let mut cache: HashMap<IpAddr, Option<ContainerInfo>> = HashMap::new();
+        for listener in aggregated.iter_mut().filter(|l| is_proxy(l)) {
+            let Some(ip) = listener
+                .cmdline
+                .as_deref()
+                .and_then(parse_docker_proxy_target)
+            else {
+                continue;
+            };
+            listener.container = cache
+                .entry(ip)
+                .or_insert_with(|| find_container_by_address(&namespaces, ip))
+                .clone();
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = aggregated;
+}
+
+/// Find the container whose network namespace has `ip` as a local address
+#[cfg(target_os = "linux")]
+fn find_container_by_address(namespaces: &[NetNamespace], ip: IpAddr) -> Option<ContainerInfo> {
+    let table = match ip {
+        IpAddr::V4(_) => "fib_trie",
+        IpAddr::V6(_) => "if_inet6",
+    };
+    namespaces
+        .iter()
+        .filter_map(|namespace| namespace.pid)
+        .find_map(|pid| {
+            let raw = std::fs::read_to_string(format!("/proc/{pid}/net/{table}")).ok()?;
+            if !netns_has_address(&raw, ip) {
+                return None;
+            }
+            parse_cgroup_container(&std::fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?)
+        })
+}
+
+/// Container address from a docker-proxy command line (`-container-ip 172.17.0.2`)
+#[cfg(any(target_os = "linux", test))]
+fn parse_docker_proxy_target(cmdline: &str) -> Option<IpAddr> {
+    let mut args = cmdline.split_whitespace();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix('-') else {
+            continue;
+        };
+        let flag = flag.strip_prefix('-').unwrap_or(flag);
+        if let Some(value) = flag.strip_prefix("container-ip=") {
+            return value.parse().ok();
+        }
+        if flag == "container-ip" {
+            return args.next()?.parse().ok();
+        }
+    }
+    None
+}
+
+/// Whether `ip` is a local address in a namespace's /proc/<pid>/net/fib_trie (IPv4)
+/// or /proc/<pid>/net/if_inet6 (IPv6)
+#[cfg(any(target_os = "linux", test))]
+fn netns_has_address(raw: &str, ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            // Leaf lines (`|-- 172.17.0.2`) are followed by their route types
+            let mut leaf = None;
+            raw.lines().any(|line| {
+                let line = line.trim_start();
+                if let Some(address) = line.strip_prefix("|-- ") {
+                    leaf = address.parse::<Ipv4Addr>().ok();
+                    return false;
+                }
+                leaf == Some(ip) && line.ends_with("host LOCAL")
+            })
+        }
+        IpAddr::V6(ip) => raw
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter_map(|hex| u128::from_str_radix(hex, 16).ok())
+            .any(|address| Ipv6Addr::from(address) == ip),
+    }
+}
+
+/// Resolve listeners held by systemd (PID 1) to their `.socket` unit and activated service
+///
+/// The role is re-inferred from the service name, since `systemd` itself says nothing
+/// about what the socket is for.
+fn attach_socket_activation(aggregated: &mut [AggregatedListener]) {
+    #[cfg(target_os = "linux")]
+    {
+        if !aggregated.iter().any(held_by_system_manager) {
+            return;
+        }
+
+        let units = load_socket_units();
+        for listener in aggregated.iter_mut().filter(|l| held_by_system_manager(l)) {
+            let Some(unit) = find_socket_unit(&units, listener) else {
+                continue;
+            };
+            let service_name = unit.service.trim_end_matches(".service");
+            let role = match listener.protocol {
+                Protocol::Unix => infer_unix_role(&listener.endpoints, service_name),
+                protocol => infer_role(listener.port, protocol, service_name),
+            };
+            if role.description != UNKNOWN_ROLE {
+                let mut role = role;
+                role.evidence = Evidence::new(EvidenceKind::SocketUnit, unit.name.as_str());
+                listener.role.consider(role);
+            }
+            listener.socket_activation = Some(SocketActivation {
+                socket: unit.name.clone(),
+                service: unit.service.clone(),
+            });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = aggregated;
+}
+
+/// Whether the system manager (PID 1) holds the listener
+///
+/// Per-user `systemd --user` managers have the same command name, but their units live
+/// in user directories that are not scanned, so they are not matched by name.
+#[cfg(any(target_os = "linux", test))]
+fn held_by_system_manager(listener: &AggregatedListener) -> bool {
+    listener.pid == Some(1)
+}
+
+/// Load `.socket` units from the systemd unit directories; earlier directories win
+#[cfg(target_os = "linux")]
+fn load_socket_units() -> Vec<SocketUnit> {
+    let mut seen = HashSet::new();
+    let mut units = Vec::new();
+
+    for dir in SYSTEMD_UNIT_DIRS {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !name.ends_with(".socket") || !seen.insert(name.clone()) {
+                continue;
+            }
+            if let Ok(raw) = std::fs::read_to_string(entry.path()) {
+                units.push(parse_socket_unit(&name, &raw));
+            }
+        }
+    }
+
+    units
+}
+
+/// Parse the `[Socket]` section of a `.socket` unit file
+///
+/// The activated service is `Service=` if set, otherwise the unit's own name with a
+/// `.service` suffix (`<name>@.service` for `Accept=yes`).
+#[cfg(any(target_os = "linux", test))]
+fn parse_socket_unit(name: &str, raw: &str) -> SocketUnit {
+    let mut in_socket = false;
+    let mut listens = Vec::new();
+    let mut service = None;
+    let mut accept = false;
+
+    for line in raw.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_socket = line == "[Socket]";
+            continue;
+        }
+        if !in_socket {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let protocol = match key.trim() {
+            "ListenStream" => Protocol::Tcp,
+            "ListenDatagram" => Protocol::Udp,
+            // SOCK_SEQPACKET listeners are AF_UNIX only
+            "ListenSequentialPacket" => Protocol::Unix,
+            "Service" => {
+                service = Some(value.to_string());
+                continue;
+            }
+            "Accept" => {
+                accept = matches!(value, "yes" | "true" | "on" | "1");
+                continue;
+            }
+            _ => continue,
+        };
+        // An empty assignment resets the list, as in systemd
+        if value.is_empty() {
+            listens.clear();
+        } else if let Some(listen) = parse_listen_address(value, protocol) {
+            listens.push(listen);
+        }
+    }
+
+    let stem = name.trim_end_matches(".socket");
+    let service = service.unwrap_or_else(|| {
+        if accept {
+            format!("{stem}@.service")
+        } else {
+            format!("{stem}.service")
+        }
+    });
+
+    SocketUnit {
+        name: name.to_string(),
+        service,
+        listens,
+    }
+}
+
+/// Parse a `Listen*=` value: a port, `addr:port`, `[v6]:port`, or a socket path
+#[cfg(any(target_os = "linux", test))]
+fn parse_listen_address(value: &str, protocol: Protocol) -> Option<ListenAddress> {
+    if value.starts_with('/') || value.starts_with('@') {
+        return Some(ListenAddress::Path(value.to_string()));
+    }
+    if value.starts_with("vsock:") || protocol == Protocol::Unix {
+        return None;
+    }
+    let port = value.rsplit(':').next()?.parse::<u16>().ok()?;
+    Some(ListenAddress::Port(protocol, port))
+}
+
+/// Find the socket unit listening on the same port/protocol or Unix path as a listener
+#[cfg(any(target_os = "linux", test))]
+fn find_socket_unit<'a>(
+    units: &'a [SocketUnit],
+    listener: &AggregatedListener,
+) -> Option<&'a SocketUnit> {
+    units.iter().find(|unit| {
+        unit.listens.iter().any(|listen| match listen {
+            ListenAddress::Path(path) => {
+                listener.protocol == Protocol::Unix && listener.endpoints.contains(path)
+            }
+            &ListenAddress::Port(protocol, port) => {
+                listener.protocol == protocol && listener.port == port
+            }
+        })
+    })
+}
+
+/// Attach the full command line, executable and working directory of each owning process
+fn attach_process_details(aggregated: &mut [AggregatedListener]) {
+    let pids: BTreeSet<u32> = aggregated.iter().filter_map(|l| l.pid).collect();
+    if pids.is_empty() {
+        return;
+    }
+
+    let details = read_process_details(&pids);
+    for listener in aggregated.iter_mut() {
+        let Some(found) = listener.pid.and_then(|pid| details.get(&pid)) else {
+            continue;
+        };
+        listener.cmdline = found.cmdline.clone();
+        listener.exe = found.exe.clone();
+        listener.cwd = found.cwd.clone();
+    }
+}
+
+/// Read process details from /proc/<pid>/{cmdline,exe,cwd}
+///
+/// exe and cwd of other users' processes need privileges and are left empty otherwise.
+#[cfg(target_os = "linux")]
+fn read_process_details(pids: &BTreeSet<u32>) -> HashMap<u32, ProcessDetails> {
+    let read_link = |pid: u32, name: &str| {
+        std::fs::read_link(format!("/proc/{pid}/{name}"))
+            .ok()
+            .map(|path| path.to_string_lossy().into_owned())
+    };
+
+    pids.iter()
+        .map(|&pid| {
+            let details = ProcessDetails {
+                cmdline: std::fs::read(format!("/proc/{pid}/cmdline"))
+                    .ok()
+                    .and_then(|raw| parse_proc_cmdline(&raw)),
+                exe: read_link(pid, "exe"),
+                cwd: read_link(pid, "cwd"),
+            };
+            (pid, details)
+        })
+        .collect()
+}
+
+/// Read process details with `ps` (command line) and `lsof` (cwd and executable)
+///
+/// Both are best-effort; a failing command leaves the corresponding fields empty.
+#[cfg(not(target_os = "linux"))]
+fn read_process_details(pids: &BTreeSet<u32>) -> HashMap<u32, ProcessDetails> {
+    let pid_list = pids
+        .iter()
+        .map(|pid| pid.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let run = |command: &str, args: &[&str]| {
+        Command::new(command)
+            .args(args)
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default()
+    };
+
+    let mut details: HashMap<u32, ProcessDetails> = HashMap::new();
+    let ps = run("ps", &["-ww", "-o", "pid=,command=", "-p", &pid_list]);
+    for (pid, cmdline) in parse_ps_commands(&ps) {
+        details.entry(pid).or_default().cmdline = Some(cmdline);
+    }
+
+    let mut lsof_args = vec!["-p", pid_list.as_str()];
+    lsof_args.extend(LSOF_PROCESS_PATH_ARGS);
+    let lsof = run("lsof", &lsof_args);
+    for (pid, (cwd, exe)) in parse_lsof_process_paths(&lsof) {
+        let entry = details.entry(pid).or_default();
+        entry.cwd = cwd;
+        entry.exe = exe;
+    }
+
+    details
+}
+
+/// Join the NUL-separated arguments of /proc/<pid>/cmdline with single spaces, matching
+/// what `ps` reports on macOS; argument boundaries are not preserved. Kernel threads have none
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_cmdline(raw: &[u8]) -> Option<String> {
+    let args: Vec<String> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (!args.is_empty()).then(|| args.join(" "))
+}
+
+/// Parse `ps -o pid=,command=` output into (pid, command line) pairs
+#[cfg(any(not(target_os = "linux"), test))]
+fn parse_ps_commands(raw: &str) -> Vec<(u32, String)> {
+    raw.lines()
+        .filter_map(|line| {
+            let (pid, cmdline) = line.trim().split_once(char::is_whitespace)?;
+            Some((pid.parse().ok()?, cmdline.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parse `lsof -d cwd,txt -Ffn` output into pid -> (cwd, exe)
+///
+/// The first `txt` entry of a process is its executable; later ones are mapped libraries.
+#[cfg(any(not(target_os = "linux"), test))]
+fn parse_lsof_process_paths(raw: &str) -> HashMap<u32, (Option<String>, Option<String>)> {
+    let mut paths: HashMap<u32, (Option<String>, Option<String>)> = HashMap::new();
+    let mut current_pid = None;
+    let mut current_fd = "";
+
+    for line in raw.lines() {
+        let (tag, value) = line.split_at(line.len().min(1));
+        match tag {
+            "p" => current_pid = value.parse::<u32>().ok(),
+            "f" => current_fd = value,
+            "n" => {
+                let Some(pid) = current_pid else {
+                    continue;
+                };
+                let entry = paths.entry(pid).or_default();
+                match current_fd {
+                    "cwd" if entry.0.is_none() => entry.0 = Some(value.to_string()),
+                    "txt" if entry.1.is_none() => entry.1 = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    paths
+}
+
+/// Find the systemd service unit from the contents of /proc/<pid>/cgroup
+///
+/// Uses the unified (`0::`) or `name=systemd` hierarchy and returns the innermost
+/// `.service` segment, so processes under `user@<uid>.service` resolve to the user unit.
+#[cfg(any(target_os = "linux", test))]
+fn parse_cgroup_unit(raw: &str) -> Option<String> {
+    raw.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let _hierarchy = fields.next()?;
+            let controllers = fields.next()?;
+            let path = fields.next()?;
+            (controllers.is_empty() || controllers == "name=systemd").then_some(path)
+        })
+        .find_map(|path| {
+            path.rsplit('/')
+                .find(|segment| segment.ends_with(".service"))
+                .map(str::to_string)
+        })
+}
+
+/// Detect a container from the contents of /proc/<pid>/cgroup
+///
+/// Recognizes systemd scopes (`docker-<id>.scope`, `libpod-<id>.scope`,
+/// `cri-containerd-<id>.scope`, `crio-<id>.scope`), cgroup v1 parents
+/// (`/docker/<id>`) and kubepods hierarchies, which also carry the pod UID.
+#[cfg(any(target_os = "linux", test))]
+fn parse_cgroup_container(raw: &str) -> Option<ContainerInfo> {
+    for line in raw.lines() {
+        // Format: hierarchy-ID:controllers:path
+        let Some(path) = line.splitn(3, ':').nth(2) else {
+            continue;
+        };
+
+        let mut pod_uid = None;
+        let mut parent = "";
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            if let Some(uid) = parse_pod_uid(segment) {
+                pod_uid = Some(uid);
+            }
+
+            let name = segment.strip_suffix(".scope").unwrap_or(segment);
+            let scoped = CGROUP_SCOPE_RUNTIMES.iter().find_map(|&(prefix, runtime)| {
+                let id = name.strip_prefix(prefix)?;
+                is_container_id(id).then_some((runtime, id))
+            });
+            let bare = is_container_id(name).then(|| {
+                CGROUP_PARENT_RUNTIMES
+                    .iter()
+                    .find(|&&(dir, _)| dir == parent)
+                    .map_or("kubernetes", |&(_, runtime)| runtime)
+            });
+
+            match (scoped, bare) {
+                (Some((runtime, id)), _) => {
+                    return Some(ContainerInfo {
+                        runtime,
+                        id: id.to_string(),
+                        pod_uid,
+                    })
+                }
+                // A bare ID outside a known parent only counts inside a pod
+                (None, Some(runtime)) if runtime != "kubernetes" || pod_uid.is_some() => {
+                    return Some(ContainerInfo {
+                        runtime,
+                        id: name.to_string(),
+                        pod_uid,
+                    })
+                }
+                _ => {}
+            }
+            parent = segment;
+        }
+    }
+
+    None
+}
+
+/// Extract a pod UID from `pod<uid>` (cgroupfs) or `kubepods-<qos>-pod<uid>.slice` (systemd)
+#[cfg(any(target_os = "linux", test))]
+fn parse_pod_uid(segment: &str) -> Option<String> {
+    let name = segment.strip_suffix(".slice").unwrap_or(segment);
+    let uid = if name.starts_with("kubepods") {
+        &name[name.rfind("-pod")? + "-pod".len()..]
+    } else {
+        name.strip_prefix("pod")?
+    };
+
+    // The systemd driver escapes dashes in the UID as underscores
+    let uid = uid.replace('_', "-");
+    let valid = uid.len() == 36 && uid.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+    valid.then_some(uid)
+}
+
+/// Container IDs are 64 lowercase hex characters
+#[cfg(any(target_os = "linux", test))]
+fn is_container_id(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Attach established connections to TCP listeners, grouped by remote address
+fn attach_connections(aggregated: &mut [AggregatedListener], connections: &[Connection]) {
+    let by_endpoints: HashMap<(SocketAddr, SocketAddr), &Connection> = connections
+        .iter()
+        .map(|c| ((c.local, c.remote), c))
+        .collect();
+
+    for listener in aggregated.iter_mut() {
+        if listener.protocol != Protocol::Tcp {
+            continue;
+        }
+
+        let mut grouped: BTreeMap<String, Vec<ConnectionPeer>> = BTreeMap::new();
+        for conn in connections {
+            if !listener_accepts(&listener.endpoints, conn.local) {
+                continue;
+            }
+
+            // A local peer shows up as the mirrored connection in the same table
+            let peer_owner = by_endpoints.get(&(conn.remote, conn.local));
+            grouped
+                .entry(conn.remote.ip().to_string())
+                .or_default()
+                .push(ConnectionPeer {
+                    endpoint: conn.remote.to_string(),
+                    pid: peer_owner.and_then(|c| c.pid),
+                    command: peer_owner.and_then(|c| c.command.clone()),
+                });
+        }
+
+        listener.connections = Some(
+            grouped
+                .into_iter()
+                .map(|(address, peers)| ConnectionGroup {
+                    address,
+                    count: peers.len(),
+                    peers,
+                })
+                .collect(),
+        );
+    }
+}
+
+/// Collect listeners using lsof command
+fn collect_listeners_from_lsof(protocols: &[Protocol]) -> Result<Vec<Listener>, WhichportError> {
+    let output = Command::new("lsof")
+        .args(LSOF_ARGS)
+        .args(lsof_protocol_args(protocols))
+        .output()
+        .map_err(|e| WhichportError::CommandFailed {
+            command: "lsof".to_string(),
+            details: e.to_string(),
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(WhichportError::CommandError {
+            command: "lsof".to_string(),
+            stderr: stderr.trim().to_string(),
+        });
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_lsof_output(&stdout))
+}
+
+/// Build lsof protocol selectors; `-sTCP:LISTEN` only constrains the TCP selector
+fn lsof_protocol_args(protocols: &[Protocol]) -> Vec<&'static str> {
+    let mut args = Vec::new();
+    for protocol in protocols {
+        match protocol {
+            Protocol::Tcp => args.extend(["-iTCP", "-sTCP:LISTEN"]),
+            Protocol::Udp => args.push("-iUDP"),
+            // Unix sockets are collected separately by collect_unix_listeners
+            Protocol::Unix => {}
+        }
+    }
+    args
+}
+
+/// Collect listeners using ss command (Linux only)
+#[cfg(target_os = "linux")]
+fn collect_listeners_from_ss(protocols: &[Protocol]) -> Result<Vec<Listener>, WhichportError> {
+    let users = load_user_names();
+    let mut listeners = Vec::new();
+
+    // One invocation per protocol keeps the column layout free of the Netid column
+    for &protocol in protocols {
+        let args = match protocol {
+            Protocol::Tcp => "-lntpeH",
+            Protocol::Udp => "-lnupeH",
+            Protocol::Unix => continue,
+        };
+        let output = Command::new("ss")
+            .arg(args)
+            .output()
+            .map_err(|e| WhichportError::CommandFailed {
+                command: "ss".to_string(),
+                details: e.to_string(),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(WhichportError::CommandError {
+                command: "ss".to_string(),
+                stderr: stderr.trim().to_string(),
+            });
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        listeners.extend(parse_ss_output(&stdout, protocol, &users));
+    }
+
+    Ok(listeners)
+}
+
+/// Collect listeners by reading /proc/net/{tcp,udp}{,6} (Linux only)
+///
+/// Returns the listeners along with non-fatal warnings from owner resolution.
+#[cfg(target_os = "linux")]
+fn collect_listeners_from_proc(
+    protocols: &[Protocol],
+) -> Result<(Vec<Listener>, Vec<String>), WhichportError> {
+    let sockets = read_proc_net_tables("/proc/net", protocols)?;
+
+    let inodes: HashSet<u64> = sockets
+        .iter()
+        .map(|s| s.inode)
+        .filter(|&i| i != 0)
+        .collect();
+    let (owners, warnings) = resolve_socket_owners(&inodes);
+    let users = load_user_names();
+    Ok((
+        kernel_sockets_to_listeners(&sockets, &owners, &users),
+        warnings,
+    ))
+}
+
+/// Read and parse the socket tables of the selected protocols under a /proc net directory
+#[cfg(target_os = "linux")]
+fn read_proc_net_tables(
+    net_dir: &str,
+    protocols: &[Protocol],
+) -> Result<Vec<KernelSocket>, WhichportError> {
+    let mut sockets = Vec::new();
+    let mut last_error = None;
+    let mut any_read = false;
+
+    for &protocol in protocols {
+        let tables = match protocol {
+            Protocol::Tcp => PROC_NET_TCP_TABLES,
+            Protocol::Udp => PROC_NET_UDP_TABLES,
+            Protocol::Unix => continue,
+        };
+        for table in tables {
+            let path = format!("{net_dir}/{table}");
+            // The v6 tables are absent when IPv6 is disabled, so one readable table is enough
+            match std::fs::read_to_string(&path) {
+                Ok(raw) => {
+                    any_read = true;
+                    sockets.extend(parse_proc_net(&raw, protocol));
+                }
+                Err(e) => {
+                    last_error = Some(WhichportError::ReadFailed {
+                        path,
+                        details: e.to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    if !any_read {
+        if let Some(err) = last_error {
+            return Err(err);
+        }
+    }
+
+    Ok(sockets)
+}
+
+/// Collect listeners from each selected network namespace (Linux only)
+///
+/// Namespaces with a live process are read through /proc/<pid>/net; named
+/// namespaces without processes are entered with setns(2) on a scratch thread.
+/// Per-namespace failures are reported as warnings.
+#[cfg(target_os = "linux")]
+fn collect_netns_listeners(
+    selector: &NetnsSelector,
+    protocols: &[Protocol],
+) -> Result<CollectionResult, WhichportError> {
+    let discovered = discover_namespaces();
+    let pid_inode = match selector {
+        NetnsSelector::Pid(pid) => Some(read_netns_inode(*pid).ok_or_else(|| {
+            WhichportError::NetnsFailed(format!("cannot read /proc/{pid}/ns/net"))
+        })?),
+        _ => None,
+    };
+    let namespaces = select_namespaces(&discovered, selector, pid_inode)?;
+
+    let mut errors = Vec::new();
+    let mut per_namespace = Vec::new();
+    for namespace in &namespaces {
+        let sockets = match (namespace.pid, &namespace.name) {
+            (Some(pid), _) => read_proc_net_tables(&format!("/proc/{pid}/net"), protocols),
+            (None, Some(name)) => read_named_netns_tables(name, protocols),
+            (None, None) => continue,
+        };
+        match sockets {
+            Ok(sockets) => per_namespace.push((namespace.id(), sockets)),
+            Err(err) => errors.push(format!("netns {}: {err}", namespace.id())),
+        }
+    }
+
+    if per_namespace.is_empty() && !errors.is_empty() {
+        return Err(WhichportError::AllMethodsFailed(errors.join(" | ")));
+    }
+
+    // Socket inodes are global, so owners are resolved once across namespaces
+    let inodes: HashSet<u64> = per_namespace
+        .iter()
+        .flat_map(|(_, sockets)| sockets.iter().map(|s| s.inode))
+        .filter(|&i| i != 0)
+        .collect();
+    let (owners, warnings) = resolve_socket_owners(&inodes);
+    errors.extend(warnings);
+    let users = load_user_names();
+
+    let mut listeners = Vec::new();
+    for (id, sockets) in per_namespace {
+        for mut listener in kernel_sockets_to_listeners(&sockets, &owners, &users) {
+            listener.netns = Some(id.clone());
+            listeners.push(listener);
+        }
+    }
+
+    Ok(CollectionResult {
+        listeners,
+        source: "proc",
+        errors,
+    })
+}
+
+/// Discover network namespaces from /proc/<pid>/ns/net links and /run/netns mounts
+#[cfg(target_os = "linux")]
+fn discover_namespaces() -> Vec<NetNamespace> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut by_inode: BTreeMap<u64, NetNamespace> = BTreeMap::new();
+
+    if let Ok(entries) = std::fs::read_dir(NETNS_RUN_DIR) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let (Ok(meta), Some(name)) = (entry.metadata(), file_name.to_str()) else {
+                continue;
+            };
+            by_inode.entry(meta.ino()).or_insert(NetNamespace {
+                inode: meta.ino(),
+                name: Some(name.to_string()),
+                pid: None,
+            });
+        }
+    }
+
+    if let Ok(entries) = std::fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let Some(inode) = read_netns_inode(pid) else {
+                continue;
+            };
+            let namespace = by_inode.entry(inode).or_insert(NetNamespace {
+                inode,
+                name: None,
+                pid: None,
+            });
+            if namespace.pid.is_none_or(|current| pid < current) {
+                namespace.pid = Some(pid);
+            }
+        }
+    }
+
+    by_inode.into_values().collect()
+}
+
+/// Read the network namespace inode of a process
+#[cfg(target_os = "linux")]
+fn read_netns_inode(pid: u32) -> Option<u64> {
+    let target = std::fs::read_link(format!("/proc/{pid}/ns/net")).ok()?;
+    parse_netns_link(target.to_str()?)
+}
+
+/// Extract the inode from a namespace link target like `net:[4026531840]`
+#[cfg(any(target_os = "linux", test))]
+fn parse_netns_link(target: &str) -> Option<u64> {
+    target
+        .strip_prefix("net:[")?
+        .strip_suffix(']')?
+        .parse::<u64>()
+        .ok()
+}
+
+/// Pick the namespaces matching a --netns selector
+///
+/// `pid_inode` is the namespace inode of the selected PID, if any.
+#[cfg(any(target_os = "linux", test))]
+fn select_namespaces(
+    discovered: &[NetNamespace],
+    selector: &NetnsSelector,
+    pid_inode: Option<u64>,
+) -> Result<Vec<NetNamespace>, WhichportError> {
+    match selector {
+        NetnsSelector::All => Ok(discovered.to_vec()),
+        NetnsSelector::Name(name) => discovered
+            .iter()
+            .find(|ns| ns.name.as_deref() == Some(name.as_str()))
+            .cloned()
+            .map(|ns| vec![ns])
+            .ok_or_else(|| WhichportError::NetnsFailed(format!("no namespace named {name}"))),
+        NetnsSelector::Pid(pid) => {
+            let inode = pid_inode.ok_or_else(|| {
+                WhichportError::NetnsFailed(format!("no namespace found for pid {pid}"))
+            })?;
+            let mut namespace = discovered
+                .iter()
+                .find(|ns| ns.inode == inode)
+                .cloned()
+                .unwrap_or(NetNamespace {
+                    inode,
+                    name: None,
+                    pid: None,
+                });
+            // Read through the requested process even if a lower PID shares the namespace
+            namespace.pid = Some(*pid);
+            Ok(vec![namespace])
+        }
+    }
+}
+
+/// Read socket tables inside a named namespace by entering it on a scratch thread
+///
+/// setns(2) only affects the calling thread, so the main thread keeps its namespace.
+#[cfg(target_os = "linux")]
+fn read_named_netns_tables(
+    name: &str,
+    protocols: &[Protocol],
+) -> Result<Vec<KernelSocket>, WhichportError> {
+    use std::os::fd::AsRawFd;
+
+    let path = format!("{NETNS_RUN_DIR}/{name}");
+    let protocols = protocols.to_vec();
+
+    std::thread::spawn(move || {
+        let file = std::fs::File::open(&path).map_err(|e| WhichportError::ReadFailed {
+            path: path.clone(),
+            details: e.to_string(),
+        })?;
+        // SAFETY: file is an open namespace file descriptor for the duration of the call
+        if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return Err(WhichportError::NetnsFailed(format!(
+                "setns {path}: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        read_proc_net_tables("/proc/thread-self/net", &protocols)
+    })
+    .join()
+    .unwrap_or_else(|_| {
+        Err(WhichportError::NetnsFailed(
+            "namespace reader thread panicked".to_string(),
+        ))
+    })
+}
+
+/// Map socket inodes to their owning processes by walking /proc/<pid>/fd
+///
+/// Processes of other users are unreadable without privileges; those are
+/// counted and reported as a single warning instead of failing collection.
+#[cfg(target_os = "linux")]
+fn resolve_socket_owners(inodes: &HashSet<u64>) -> (HashMap<u64, SocketOwner>, Vec<String>) {
+    let mut owners = HashMap::new();
+    let mut warnings = Vec::new();
+    let mut denied = 0usize;
+
+    if inodes.is_empty() {
+        return (owners, warnings);
+    }
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(e) => {
+            warnings.push(format!("failed to read /proc: {e}"));
+            return (owners, warnings);
+        }
+    };
+
+    for entry in entries.flatten() {
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        let fds = match std::fs::read_dir(format!("/proc/{pid}/fd")) {
+            Ok(fds) => fds,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                denied += 1;
+                continue;
+            }
+            // The process exited while we were scanning
+            Err(_) => continue,
+        };
+
+        let mut owned = Vec::new();
+        for fd in fds.flatten() {
+            let target = match std::fs::read_link(fd.path()) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            if let Some(inode) = target.to_str().and_then(parse_socket_inode_link) {
+                if inodes.contains(&inode) && !owners.contains_key(&inode) {
+                    owned.push(inode);
+                }
+            }
+        }
+
+        if owned.is_empty() {
+            continue;
+        }
+
+        let owner = read_socket_owner(pid);
+        for inode in owned {
+            owners.insert(inode, owner.clone());
+        }
+    }
+
+    if denied > 0 {
+        warnings.push(format!(
+            "permission denied reading /proc/<pid>/fd for {denied} processes; owners of their sockets are unknown"
+        ));
+    }
+
+    (owners, warnings)
+}
+
+/// Read command name and user of a process from /proc/<pid>/{comm,status}
+#[cfg(target_os = "linux")]
+fn read_socket_owner(pid: u32) -> SocketOwner {
+    let command = std::fs::read_to_string(format!("/proc/{pid}/comm"))
+        .map(|c| c.trim_end().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    let uid = std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .ok()
+        .and_then(|status| parse_status_uid(&status));
+
+    SocketOwner { pid, command, uid }
+}
+
+/// Extract the inode from an fd link target like `socket:[12345]`
+#[cfg(any(target_os = "linux", test))]
+fn parse_socket_inode_link(target: &str) -> Option<u64> {
+    target
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse::<u64>()
+        .ok()
+}
+
+/// Extract the real UID from /proc/<pid>/status content
+#[cfg(any(target_os = "linux", test))]
+fn parse_status_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|uid| uid.parse::<u32>().ok())
+}
+
+/// Parse a /proc/net/{tcp,udp}{,6} table, keeping listening sockets only
+///
+/// For UDP, "listening" means bound and unconnected, matching `ss -lu`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_net(raw: &str, protocol: Protocol) -> Vec<KernelSocket> {
+    let listen_state = match protocol {
+        Protocol::Tcp => PROC_TCP_LISTEN,
+        Protocol::Udp => PROC_UDP_UNCONN,
+        Protocol::Unix => return Vec::new(),
+    };
+    let mut out = Vec::new();
+
+    // First line is the column header
+    for line in raw.lines().skip(1) {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 10 || tokens[3] != listen_state {
+            continue;
+        }
+
+        let (endpoint, port) = match decode_proc_address(tokens[1]) {
+            Some(addr) => (addr.to_string(), addr.port()),
+            None => continue,
+        };
+        let uid = match tokens[7].parse::<u32>() {
+            Ok(uid) => Some(uid),
+            Err(_) => continue,
+        };
+        let inode = tokens[9].parse::<u64>().unwrap_or(0);
+
+        out.push(KernelSocket {
+            port,
+            protocol,
+            endpoint,
+            uid,
+            inode,
+        });
+    }
+
+    out
+}
+
+/// Parse /proc/net/unix content, keeping listening stream/seqpacket sockets with a path
+///
+/// Abstract socket names are shown with a leading `@`, as ss does.
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_net_unix(raw: &str) -> Vec<KernelSocket> {
+    let mut out = Vec::new();
+
+    // Columns: Num RefCount Protocol Flags Type St Inode [Path]
+    for line in raw.lines().skip(1) {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 8 {
+            continue;
+        }
+
+        let flags = u32::from_str_radix(tokens[3], 16).unwrap_or(0);
+        if flags & UNIX_FLAG_ACCEPTCON == 0 || !UNIX_LISTEN_TYPES.contains(&tokens[4]) {
+            continue;
+        }
+        let inode = match tokens[6].parse::<u64>() {
+            Ok(inode) => inode,
+            Err(_) => continue,
+        };
+
+        out.push(KernelSocket {
+            port: 0,
+            protocol: Protocol::Unix,
+            endpoint: tokens[7..].join(" "),
+            uid: None,
+            inode,
+        });
+    }
+
+    out
+}
+
+/// Decode a hex `ADDR:PORT` pair from /proc/net into a socket address
+///
+/// Addresses are printed as 32-bit words in host byte order; ports are big-endian.
+#[cfg(any(target_os = "linux", test))]
+fn decode_proc_address(raw: &str) -> Option<SocketAddr> {
+    let (addr_hex, port_hex) = raw.split_once(':')?;
+    let port = u16::from_str_radix(port_hex, 16).ok()?;
+
+    let mut bytes = Vec::with_capacity(16);
+    for chunk in addr_hex.as_bytes().chunks(8) {
+        let word = std::str::from_utf8(chunk).ok()?;
+        let word = u32::from_str_radix(word, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+
+    socket_addr_from_bytes(&bytes, port)
+}
+
+/// Build a socket address from raw network-order address bytes and a port
+#[cfg(any(target_os = "linux", test))]
+fn socket_addr_from_bytes(addr: &[u8], port: u16) -> Option<SocketAddr> {
+    let ip = match addr.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(addr).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(addr).ok()?),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Collect listeners through NETLINK_SOCK_DIAG inet_diag dumps (Linux only)
+///
+/// Returns the listeners along with non-fatal warnings from owner resolution.
+#[cfg(target_os = "linux")]
+fn collect_listeners_from_netlink(
+    protocols: &[Protocol],
+) -> Result<(Vec<Listener>, Vec<String>), WhichportError> {
+    let mut sockets = Vec::new();
+    for &protocol in protocols {
+        // Bound, unconnected UDP sockets sit in TCP_CLOSE, as in /proc/net/udp
+        let (ipproto, states) = match protocol {
+            Protocol::Tcp => (libc::IPPROTO_TCP as u8, 1 << TCP_STATE_LISTEN),
+            Protocol::Udp => (libc::IPPROTO_UDP as u8, 1 << TCP_STATE_CLOSE),
+            Protocol::Unix => continue,
+        };
+        for family in [libc::AF_INET as u8, libc::AF_INET6 as u8] {
+            let batch = query_inet_diag(family, ipproto, states, protocol)?;
+            sockets.extend(batch);
+        }
+    }
+
+    let inodes: HashSet<u64> = sockets
+        .iter()
+        .map(|s| s.inode)
+        .filter(|&i| i != 0)
+        .collect();
+    let (owners, warnings) = resolve_socket_owners(&inodes);
+    let users = load_user_names();
+    Ok((
+        kernel_sockets_to_listeners(&sockets, &owners, &users),
+        warnings,
+    ))
+}
+
+/// Send one inet_diag dump request and read replies until NLMSG_DONE
+#[cfg(target_os = "linux")]
+fn query_inet_diag(
+    family: u8,
+    ipproto: u8,
+    states: u32,
+    protocol: Protocol,
+) -> Result<Vec<KernelSocket>, WhichportError> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let netlink_err = |call: &str| {
+        WhichportError::NetlinkFailed(format!("{call}: {}", std::io::Error::last_os_error()))
+    };
+
+    // SAFETY: plain socket(2) call; the returned fd is owned immediately below
+    let raw_fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_SOCK_DIAG,
+        )
+    };
+    if raw_fd < 0 {
+        return Err(netlink_err("socket"));
+    }
+    // SAFETY: raw_fd is a freshly created, valid descriptor not owned elsewhere
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+
+    let request = build_inet_diag_request(family, ipproto, states, 1);
+    // SAFETY: sockaddr_nl is plain old data; zeroed means "the kernel" as destination
+    let mut kernel: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+    // SAFETY: request and kernel outlive the call and their lengths are passed explicitly
+    let sent = unsafe {
+        libc::sendto(
+            fd.as_raw_fd(),
+            request.as_ptr().cast(),
+            request.len(),
+            0,
+            (&kernel as *const libc::sockaddr_nl).cast(),
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(netlink_err("sendto"));
+    }
+
+    let mut sockets = Vec::new();
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        // SAFETY: buf is valid for writes of buf.len() bytes
+        let received = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if received < 0 {
+            return Err(netlink_err("recv"));
+        }
+        if received == 0 {
+            break;
+        }
+
+        let batch = parse_inet_diag_messages(&buf[..received as usize], protocol)
+            .map_err(WhichportError::NetlinkFailed)?;
+        sockets.extend(batch.sockets);
+        if batch.done {
+            break;
+        }
+    }
+
+    Ok(sockets)
+}
+
+/// Build an nlmsghdr + inet_diag_req_v2 dump request
+#[cfg(any(target_os = "linux", test))]
+fn build_inet_diag_request(family: u8, protocol: u8, states: u32, seq: u32) -> Vec<u8> {
+    let len = NLMSG_HEADER_LEN + INET_DIAG_REQ_V2_LEN;
+    let mut msg = Vec::with_capacity(len);
+
+    // struct nlmsghdr
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    msg.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+
+    // struct inet_diag_req_v2: family, protocol, ext, pad, states, then a zeroed sockid
+    msg.extend_from_slice(&[family, protocol, 0, 0]);
+    msg.extend_from_slice(&states.to_ne_bytes());
+    msg.resize(len, 0);
+
+    msg
+}
+
+/// Sockets decoded from one netlink receive buffer
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, Default)]
+struct InetDiagBatch {
+    sockets: Vec<KernelSocket>,
+    /// NLMSG_DONE was seen, so the dump is complete
+    done: bool,
+}
+
+/// Parse raw netlink bytes carrying SOCK_DIAG_BY_FAMILY replies
+#[cfg(any(target_os = "linux", test))]
+fn parse_inet_diag_messages(buf: &[u8], protocol: Protocol) -> Result<InetDiagBatch, String> {
+    let mut batch = InetDiagBatch::default();
+    let mut offset = 0;
+
+    while offset + NLMSG_HEADER_LEN <= buf.len() {
+        let msg_len = read_ne_u32(buf, offset) as usize;
+        let msg_type = u16::from_ne_bytes([buf[offset + 4], buf[offset + 5]]);
+        if msg_len < NLMSG_HEADER_LEN || offset + msg_len > buf.len() {
+            return Err(format!("truncated netlink message ({msg_len} bytes)"));
+        }
+        let payload = &buf[offset + NLMSG_HEADER_LEN..offset + msg_len];
+
+        match msg_type {
+            NLMSG_DONE => {
+                batch.done = true;
+                return Ok(batch);
+            }
+            NLMSG_ERROR => {
+                let errno = payload
+                    .get(..4)
+                    .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .unwrap_or(0);
+                return Err(format!(
+                    "kernel returned error: {}",
+                    std::io::Error::from_raw_os_error(-errno)
+                ));
+            }
+            SOCK_DIAG_BY_FAMILY => {
+                if let Some(socket) = parse_inet_diag_msg(payload, protocol) {
+                    batch.sockets.push(socket);
+                }
+            }
+            _ => {}
+        }
+
+        // Messages are padded to 4-byte alignment
+        offset += (msg_len + 3) & !3;
+    }
+
+    Ok(batch)
+}
+
+/// Decode a single struct inet_diag_msg payload
+///
+/// The message does not carry the transport protocol, so the caller supplies it.
+#[cfg(any(target_os = "linux", test))]
+fn parse_inet_diag_msg(payload: &[u8], protocol: Protocol) -> Option<KernelSocket> {
+    if payload.len() < INET_DIAG_MSG_LEN {
+        return None;
+    }
+
+    let family = payload[0];
+    // struct inet_diag_sockid starts at offset 4: sport, dport (big-endian), src[16], dst[16]
+    let port = u16::from_be_bytes([payload[4], payload[5]]);
+    let addr_len = match i32::from(family) {
+        AF_INET_FAMILY => 4,
+        AF_INET6_FAMILY => 16,
+        _ => return None,
+    };
+    let endpoint = socket_addr_from_bytes(&payload[8..8 + addr_len], port)?.to_string();
+
+    // expires, rqueue, wqueue precede uid and inode
+    let uid = Some(read_ne_u32(payload, 64));
+    let inode = u64::from(read_ne_u32(payload, 68));
+
+    Some(KernelSocket {
+        port,
+        protocol,
+        endpoint,
+        uid,
+        inode,
+    })
+}
+
+/// Read a native-endian u32 at the given offset
+#[cfg(any(target_os = "linux", test))]
+fn read_ne_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// Convert /proc socket entries into listeners using resolved socket owners
+///
+/// Sockets without a known owner keep only the UID from the socket table.
+#[cfg(any(target_os = "linux", test))]
+fn kernel_sockets_to_listeners(
+    sockets: &[KernelSocket],
+    owners: &HashMap<u64, SocketOwner>,
+    users: &HashMap<u32, String>,
+) -> Vec<Listener> {
+    let mut out = Vec::new();
+    let mut dedup = HashSet::new();
+
+    for socket in sockets {
+        let record = match owners.get(&socket.inode) {
+            Some(owner) => Listener {
+                port: socket.port,
+                protocol: socket.protocol,
+                pid: Some(owner.pid),
+                command: owner.command.clone(),
+                user: user_display(owner.uid, users),
+                endpoint: socket.endpoint.clone(),
+                netns: None,
+            },
+            None => Listener {
+                port: socket.port,
+                protocol: socket.protocol,
+                pid: None,
+                command: "unknown".to_string(),
+                user: user_display(socket.uid, users),
+                endpoint: socket.endpoint.clone(),
+                netns: None,
+            },
+        };
+
+        if dedup.insert(record.clone()) {
+            out.push(record);
+        }
+    }
+
+    out.sort_by_key(|l| (l.port, l.pid.unwrap_or(0)));
+    out
+}
+
+/// Parse lsof -F output format
+fn parse_lsof_output(raw: &str) -> Vec<Listener> {
+    let mut current_pid: Option<u32> = None;
+    let mut current_command: Option<String> = None;
+    let mut current_user: Option<String> = None;
+    let mut current_protocol = Protocol::Tcp;
+
+    let mut out = Vec::new();
+    let mut dedup = HashSet::new();
+
+    for line in raw.lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let (tag, value) = line.split_at(1);
+        match tag {
+            "p" => current_pid = value.parse::<u32>().ok(),
+            "c" => current_command = Some(value.to_string()),
+            "L" => current_user = Some(value.to_string()),
+            "u" if current_user.is_none() => current_user = Some(value.to_string()),
+            "P" => {
+                current_protocol = match value {
+                    "UDP" => Protocol::Udp,
+                    _ => Protocol::Tcp,
+                }
+            }
+            "n" => {
+                // Connected UDP sockets show a peer (`local->remote`); they are not listeners
+                if value.contains("->") {
+                    continue;
+                }
+
+                let port = match parse_port_from_endpoint(value) {
+                    Some(port) => port,
+                    None => continue,
+                };
+
+                if let (Some(command), Some(user)) =
+                    (current_command.as_ref(), current_user.as_ref())
+                {
+                    let record = Listener {
+                        port,
+                        protocol: current_protocol,
+                        pid: current_pid,
+                        command: command.clone(),
+                        user: user.clone(),
+                        endpoint: value.to_string(),
+                        netns: None,
+                    };
+
+                    if dedup.insert(record.clone()) {
+                        out.push(record);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out.sort_by_key(|l| (l.port, l.pid.unwrap_or(0)));
+    out
+}
+
+/// Parse lsof -U -F output, keeping sockets bound to a filesystem path
+///
+/// lsof does not report a listen state for Unix sockets, so this is best-effort:
+/// connected client ends (`->0x...`) and unnamed sockets are skipped.
+#[cfg(any(not(target_os = "linux"), test))]
+fn parse_lsof_unix_output(raw: &str) -> Vec<Listener> {
+    let mut current_pid: Option<u32> = None;
+    let mut current_command: Option<String> = None;
+    let mut current_user: Option<String> = None;
+
+    let mut out = Vec::new();
+    let mut dedup = HashSet::new();
+
+    for line in raw.lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let (tag, value) = line.split_at(1);
+        match tag {
+            "p" => current_pid = value.parse::<u32>().ok(),
+            "c" => current_command = Some(value.to_string()),
+            "L" => current_user = Some(value.to_string()),
+            "u" if current_user.is_none() => current_user = Some(value.to_string()),
+            "n" if value.starts_with('/') && !value.contains("->") => {
+                if let (Some(command), Some(user)) =
+                    (current_command.as_ref(), current_user.as_ref())
+                {
+                    let record = Listener {
+                        port: 0,
+                        protocol: Protocol::Unix,
+                        pid: current_pid,
+                        command: command.clone(),
+                        user: user.clone(),
+                        endpoint: value.to_string(),
+                        netns: None,
+                    };
+
+                    if dedup.insert(record.clone()) {
+                        out.push(record);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out.sort_by_key(|l| (l.pid.unwrap_or(0), l.endpoint.clone()));
+    out
+}
+
+/// Parse ss output format (Linux)
+///
+/// `users` maps UIDs from the extended (`-e`) columns to user names.
+#[cfg(any(target_os = "linux", test))]
+fn parse_ss_output(raw: &str, protocol: Protocol, users: &HashMap<u32, String>) -> Vec<Listener> {
+    let mut out = Vec::new();
+    let mut dedup = HashSet::new();
+
+    for line in raw.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 4 {
+            continue;
+        }
+
+        let endpoint = tokens[3];
+        let port = match parse_port_from_endpoint(endpoint) {
+            Some(port) => port,
+            None => continue,
+        };
+
+        let proc_blob = if tokens.len() > 5 {
+            tokens[5..].join(" ")
+        } else {
+            String::new()
+        };
+
+        let (pid, command) = parse_ss_process_info(&proc_blob);
+        let uid = parse_ss_uid(&proc_blob);
+        let record = Listener {
+            port,
+            protocol,
+            pid,
+            command,
+            user: user_display(uid, users),
+            endpoint: endpoint.to_string(),
+            netns: None,
+        };
+
+        if dedup.insert(record.clone()) {
+            out.push(record);
+        }
+    }
+
+    out.sort_by_key(|l| (l.port, l.pid.unwrap_or(0)));
+    out
+}
+
+/// Parse process information from ss output
+#[cfg(any(target_os = "linux", test))]
+fn parse_ss_process_info(raw: &str) -> (Option<u32>, String) {
+    let mut command = "unknown".to_string();
+    let mut pid = None;
+
+    // Extract command name from quoted string
+    if let Some(start) = raw.find('"') {
+        let remain = &raw[start + 1..];
+        if let Some(end) = remain.find('"') {
+            command = remain[..end].to_string();
+        }
+    }
+
+    // Extract PID from pid= pattern
+    if let Some(idx) = raw.find("pid=") {
+        let digits: String = raw[idx + 4..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        pid = digits.parse::<u32>().ok();
+    }
+
+    (pid, command)
+}
+
+/// Extract the socket owner UID from ss extended info
+///
+/// ss only prints `uid:` for non-root sockets, so an `ino:` field without
+/// a `uid:` field means the socket belongs to root.
+#[cfg(any(target_os = "linux", test))]
+fn parse_ss_uid(raw: &str) -> Option<u32> {
+    let mut extended = false;
+    for token in raw.split_whitespace() {
+        if let Some(uid) = token.strip_prefix("uid:") {
+            return uid.parse::<u32>().ok();
+        }
+        if token.starts_with("ino:") {
+            extended = true;
+        }
+    }
+    extended.then_some(0)
+}
+
+/// Load UID to user name mappings from /etc/passwd
+#[cfg(target_os = "linux")]
+fn load_user_names() -> HashMap<u32, String> {
+    std::fs::read_to_string("/etc/passwd")
+        .map(|raw| parse_passwd(&raw))
+        .unwrap_or_default()
+}
+
+/// Parse passwd(5) content into a UID to user name map
+#[cfg(any(target_os = "linux", test))]
+fn parse_passwd(raw: &str) -> HashMap<u32, String> {
+    let mut users = HashMap::new();
+    for line in raw.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        if let Ok(uid) = fields[2].parse::<u32>() {
+            // Keep the first entry when several names share a UID
+            users.entry(uid).or_insert_with(|| fields[0].to_string());
+        }
+    }
+    users
+}
+
+/// Display a UID as a user name, falling back to the numeric UID like lsof does
+#[cfg(any(target_os = "linux", test))]
+fn user_display(uid: Option<u32>, users: &HashMap<u32, String>) -> String {
+    match uid {
+        Some(uid) => users.get(&uid).cloned().unwrap_or_else(|| uid.to_string()),
+        None => "-".to_string(),
+    }
+}
+
+/// Extract port number from endpoint string
+fn parse_port_from_endpoint(endpoint: &str) -> Option<u16> {
+    if let Some(idx) = endpoint.rfind(':') {
+        let port_str = &endpoint[idx + 1..];
+        return port_str.parse::<u16>().ok();
+    }
+    None
+}
+
+/// Infer the role of a service based on port, protocol and command name
+///
+/// The most specific command rule wins over the port rule; every other match is kept
+/// as an alternative.
+fn infer_role(port: u16, protocol: Protocol, command: &str) -> Role {
+    let cmd = command.to_ascii_lowercase();
+
+    // Command-based rules first (higher priority), most specific first
+    let mut candidates: Vec<Role> = matching_rules(&COMMAND_RULES, &cmd)
+        .into_iter()
+        .map(|rule| rule.role(EvidenceKind::CommandRule))
+        .collect();
+
+    // Then port-based rules; well-known ports differ between TCP and UDP
+    let port_rule = match protocol {
+        Protocol::Tcp => PORT_RULES
+            .iter()
+            .find(|rule| rule.0 == port)
+            .map(|&(_, description, confidence, _)| (description, confidence)),
+        Protocol::Udp => UDP_PORT_RULES
+            .iter()
+            .find(|rule| rule.0 == port)
+            .map(|&(_, description, confidence)| (description, confidence)),
+        Protocol::Unix => None,
+    };
+    candidates.extend(port_rule.map(|(description, confidence)| {
+        Role::new(
+            description,
+            confidence,
+            Evidence::new(EvidenceKind::PortRule, port.to_string()),
+        )
+    }));
+
+    let mut candidates = candidates.into_iter();
+    let Some(mut role) = candidates.next() else {
+        return Role::unknown();
+    };
+    for candidate in candidates {
+        role.add_alternative(candidate.candidate());
+    }
+    role
+}
+
+/// Attach registered service names from /etc/services, if the file is available
+fn attach_service_names(aggregated: &mut [AggregatedListener]) {
+    let Ok(raw) = std::fs::read_to_string(SERVICES_PATH) else {
+        return;
+    };
+    apply_service_names(aggregated, &parse_services(&raw));
+}
+
+/// Set the `service` field and give still-unknown roles a low-confidence registry role;
+/// known roles keep the registry name as an alternative
+fn apply_service_names(
+    aggregated: &mut [AggregatedListener],
+    services: &HashMap<(Protocol, u16), String>,
+) {
+    for listener in aggregated.iter_mut() {
+        let Some(name) = services.get(&(listener.protocol, listener.port)) else {
+            continue;
+        };
+        let registered = Role::new(
+            format!("{name} (IANA registered)"),
+            Confidence::Low,
+            Evidence::new(EvidenceKind::ServiceRegistry, name.as_str()),
+        );
+        if listener.role.description == UNKNOWN_ROLE {
+            listener.role.supersede(registered);
+        } else {
+            listener.role.add_alternative(registered.candidate());
+        }
+        listener.service = Some(name.clone());
+    }
+}
+
+/// Parse /etc/services lines (`name port/proto [aliases] [# comment]`); the first entry wins
+fn parse_services(raw: &str) -> HashMap<(Protocol, u16), String> {
+    let mut services = HashMap::new();
+    for line in raw.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(port_proto)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some((port, proto)) = port_proto.split_once('/') else {
+            continue;
+        };
+        let protocol = match proto {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => continue,
+        };
+        let Ok(port) = port.parse::<u16>() else {
+            continue;
+        };
+        services
+            .entry((protocol, port))
+            .or_insert_with(|| name.to_string());
+    }
+    services
+}
+
+/// Load user role rules from --rules, or the default path if that file exists
+fn load_user_rules(path: Option<&Path>) -> Result<Vec<UserRule>, WhichportError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_rules_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Vec::new()),
+        },
+    };
+
+    let rules_error = |details: String| WhichportError::RulesFailed {
+        path: path.display().to_string(),
+        details,
+    };
+    let raw = std::fs::read_to_string(&path).map_err(|e| rules_error(e.to_string()))?;
+    let is_json = path.extension().is_some_and(|ext| ext == "json");
+    parse_user_rules(&raw, is_json).map_err(rules_error)
+}
+
+/// `$XDG_CONFIG_HOME/whichport/rules.toml`, falling back to `~/.config`
+fn default_rules_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join(USER_RULES_FILE))
+}
+
+/// Parse and validate a rules file
+fn parse_user_rules(raw: &str, is_json: bool) -> Result<Vec<UserRule>, String> {
+    let file: RawRulesFile = if is_json {
+        serde_json::from_str(raw).map_err(|e| e.to_string())?
+    } else {
+        toml::from_str(raw).map_err(|e| e.to_string())?
+    };
+
+    file.rules
+        .into_iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            validate_user_rule(rule).map_err(|e| format!("rule {}: {e}", index + 1))
+        })
+        .collect()
+}
+
+/// Check a raw rule and compile its cmdline regex
+fn validate_user_rule(rule: RawUserRule) -> Result<UserRule, String> {
+    if rule.command.is_none()
+        && rule.port.is_none()
+        && rule.port_range.is_none()
+        && rule.cmdline.is_none()
+        && rule.user.is_none()
+    {
+        return Err("needs at least one of command, port, port_range, cmdline, user".to_string());
+    }
+
+    let port_range = match rule.port_range {
+        Some([lo, hi]) if lo > hi => return Err(format!("port_range {lo}-{hi} is reversed")),
+        Some([lo, hi]) => Some((lo, hi)),
+        None => None,
+    };
+    let cmdline = rule
+        .cmdline
+        .map(|pattern| Regex::new(&pattern).map_err(|e| format!("invalid cmdline regex: {e}")))
+        .transpose()?;
+    let command = rule
+        .command
+        .map(|pattern| {
+            // Command names are compared lowercased, so literal patterns are lowercased too
+            let pattern = match rule.command_match {
+                MatchMode::Regex => pattern,
+                _ => pattern.to_ascii_lowercase(),
+            };
+            TextMatcher::new(rule.command_match, &pattern)
+                .map_err(|e| format!("invalid command pattern: {e}"))
+        })
+        .transpose()?;
+    let confidence = match rule.confidence.as_deref() {
+        None | Some("high") => Confidence::High,
+        Some("medium") => Confidence::Medium,
+        Some("low") => Confidence::Low,
+        Some(other) => return Err(format!("unknown confidence: {other}")),
+    };
+
+    Ok(UserRule {
+        command,
+        priority: rule.priority,
+        port: rule.port,
+        port_range,
+        cmdline,
+        user: rule.user,
+        description: rule.description,
+        confidence,
+    })
+}
+
+/// Apply the highest-ranked matching user rule to each listener, overriding built-in roles
+///
+/// Ties keep file order.
+fn apply_user_rules(aggregated: &mut [AggregatedListener], rules: &[UserRule]) {
+    for listener in aggregated.iter_mut() {
+        let best = rules
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, rule)| rule.matches(listener))
+            .max_by_key(|(_, rule)| rule.rank());
+        if let Some((index, rule)) = best {
+            listener.role.supersede(Role::new(
+                rule.description.clone(),
+                rule.confidence,
+                Evidence::new(EvidenceKind::UserRule, format!("rule {}", index + 1)),
+            ));
+        }
+    }
+}
+
+/// Probe TCP listeners whose role is still unknown, in parallel
+///
+/// A detected protocol replaces the role with confidence "confirmed".
+fn probe_unknown_listeners(aggregated: &mut [AggregatedListener]) {
+    let targets: Vec<_> = aggregated
+        .iter_mut()
+        .filter(|listener| {
+            listener.protocol == Protocol::Tcp && listener.role.description == UNKNOWN_ROLE
+        })
+        .filter_map(|listener| Some((probe_target(&listener.endpoints)?, listener)))
+        .collect();
+
+    run_bounded(targets, |(target, listener)| {
+        if let Some(result) = probe_service(target) {
+            listener.role.supersede(Role::new(
+                probe_role_description(result.protocol),
+                Confidence::Confirmed,
+                Evidence::new(EvidenceKind::Probe, result.protocol),
+            ));
+            listener.probe = Some(result);
+        }
+    });
+}
+
+/// Run `work` on each item using at most PROBE_WORKERS threads; results keep input order
+fn run_bounded<T: Send, R: Send>(items: Vec<T>, work: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let workers = PROBE_WORKERS.min(items.len());
+    let queue = Mutex::new(items.into_iter().enumerate());
+
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while let Some((index, item)) = queue.lock().ok().and_then(|mut q| q.next()) {
+                        done.push((index, work(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Pick a local address to connect to; wildcard binds are reached through loopback
+fn probe_target(endpoints: &[String]) -> Option<SocketAddr> {
+    endpoints.iter().find_map(|endpoint| {
+        if let Some(port) = endpoint.strip_prefix("*:") {
+            return Some(SocketAddr::new(
+                Ipv4Addr::LOCALHOST.into(),
+                port.parse().ok()?,
+            ));
+        }
+        let mut addr = parse_scoped_endpoint(endpoint)?;
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+        Some(addr)
+    })
+}
+
+/// Parse `ip:port`, including interface-scoped endpoints as printed by ss
+///
+/// The scope is dropped for IPv4 (`127.0.0.53%lo:53`) and becomes the scope id for
+/// IPv6 (`[fe80::1%eth0]:80`), which link-local addresses need to be reachable.
+fn parse_scoped_endpoint(endpoint: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = endpoint.parse() {
+        return Some(addr);
+    }
+    let (host, port) = endpoint.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    if let Some(host) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        let (ip, scope) = host.split_once('%')?;
+        let addr = SocketAddrV6::new(ip.parse().ok()?, port, 0, interface_index(scope)?);
+        return Some(addr.into());
+    }
+    let (ip, _) = host.split_once('%')?;
+    Some(SocketAddr::new(IpAddr::V4(ip.parse().ok()?), port))
+}
+
+/// Interface index for an IPv6 scope; numeric scopes are used as-is
+fn interface_index(scope: &str) -> Option<u32> {
+    if let Ok(index) = scope.parse() {
+        return Some(index);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::read_to_string(format!("/sys/class/net/{scope}/ifindex"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    // lsof prints macOS scopes numerically or embeds them in the address
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Identify the protocol spoken at `addr`
+///
+/// Servers that greet first (SSH, MySQL) are detected without sending anything;
+/// otherwise HTTP, TLS, PostgreSQL and Redis requests are tried on fresh connections.
+fn probe_service(addr: SocketAddr) -> Option<ProbeResult> {
+    if let Some(result) = probe_exchange(addr, None).and_then(|reply| parse_greeting(&reply)) {
+        return Some(result);
+    }
+
+    let client_hello = build_tls_client_hello();
+    let probes: [(&[u8], ReplyParser); 4] = [
+        (HTTP_HEAD_REQUEST, parse_http_reply),
+        (&client_hello, parse_tls_reply),
+        (PG_SSL_REQUEST, parse_postgres_reply),
+        (REDIS_PING, parse_redis_reply),
+    ];
+    for (request, parse) in probes {
+        if let Some(result) = probe_exchange(addr, Some(request)).and_then(|reply| parse(&reply)) {
+            return Some(result);
+        }
+    }
+
+    None
+}
+
+/// Parser that recognizes a protocol from a probe reply
+type ReplyParser = fn(&[u8]) -> Option<ProbeResult>;
+
+/// Connect, optionally send a request, and read the first reply within the probe timeouts
+fn probe_exchange(addr: SocketAddr, request: Option<&[u8]>) -> Option<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&addr, PROBE_CONNECT_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(PROBE_READ_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(PROBE_READ_TIMEOUT)).ok()?;
+    if let Some(request) = request {
+        stream.write_all(request).ok()?;
+    }
+
+    let mut buf = vec![0; PROBE_MAX_REPLY];
+    let n = stream.read(&mut buf).ok()?;
+    (n > 0).then(|| {
+        buf.truncate(n);
+        buf
+    })
+}
+
+/// Role description for a probed protocol
+fn probe_role_description(protocol: &str) -> &'static str {
+    match protocol {
+        "http" => "HTTP server",
+        "tls" => "TLS service",
+        "redis" => "Redis server",
+        "postgresql" => "PostgreSQL database",
+        "mysql" => "MySQL database",
+        "ssh" => "SSH server",
+        _ => UNKNOWN_ROLE,
+    }
+}
+
+/// Recognize an unsolicited server greeting (SSH version line, MySQL handshake)
+fn parse_greeting(reply: &[u8]) -> Option<ProbeResult> {
+    if reply.starts_with(b"SSH-") {
+        let line = String::from_utf8_lossy(reply);
+        return Some(ProbeResult {
+            protocol: "ssh",
+            banner: line.lines().next().map(|l| l.trim().to_string()),
+        });
+    }
+
+    // MySQL handshake: 3-byte length, sequence 0, protocol version 10, NUL-terminated version
+    if reply.len() > 5 && reply[3] == 0 && reply[4] == 0x0a {
+        let version = reply[5..].split(|&b| b == 0).next()?;
+        let version = std::str::from_utf8(version).ok()?;
+        if !version.is_empty() && version.chars().all(|c| c.is_ascii_graphic()) {
+            return Some(ProbeResult {
+                protocol: "mysql",
+                banner: Some(version.to_string()),
+            });
+        }
+    }
+
+    None
+}
+
+/// Recognize an HTTP response; the banner is the `Server` header, else the status line
+fn parse_http_reply(reply: &[u8]) -> Option<ProbeResult> {
+    if !reply.starts_with(b"HTTP/") {
+        return None;
+    }
+    let text = String::from_utf8_lossy(reply);
+    let mut lines = text.lines();
+    let status = lines.next()?.trim().to_string();
+    let server = lines
+        .take_while(|line| !line.trim().is_empty())
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("server")
+                .then(|| value.trim().to_string())
+        });
+
+    Some(ProbeResult {
+        protocol: "http",
+        banner: Some(server.unwrap_or(status)),
+    })
+}
+
+/// Recognize a TLS record (handshake or alert) in reply to a ClientHello
+fn parse_tls_reply(reply: &[u8]) -> Option<ProbeResult> {
+    match reply {
+        [0x15 | 0x16, 0x03, minor, ..] if *minor <= 0x04 => Some(ProbeResult {
+            protocol: "tls",
+            banner: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Recognize the single-byte PostgreSQL answer to an SSLRequest
+fn parse_postgres_reply(reply: &[u8]) -> Option<ProbeResult> {
+    let banner = match reply {
+        b"S" => "SSL supported",
+        b"N" => "SSL not supported",
+        _ => return None,
+    };
+    Some(ProbeResult {
+        protocol: "postgresql",
+        banner: Some(banner.to_string()),
+    })
+}
+
+/// Recognize a Redis reply to PING, including auth-required errors
+fn parse_redis_reply(reply: &[u8]) -> Option<ProbeResult> {
+    let text = String::from_utf8_lossy(reply);
+    let line = text.lines().next()?.trim();
+    let is_redis = line == "+PONG" || line.starts_with("-NOAUTH") || line.starts_with("-DENIED");
+    is_redis.then(|| ProbeResult {
+        protocol: "redis",
+        banner: Some(line.to_string()),
+    })
+}
+
+/// Fetch / from TCP listeners in parallel and attach HTTP fingerprints
+///
+/// A matching HTTP rule refines the role by confidence. Roles from user rules are kept,
+/// and a role confirmed as plain HTTP by --probe is replaced by the more specific match.
+fn fingerprint_http_listeners(aggregated: &mut [AggregatedListener]) {
+    let targets: Vec<_> = aggregated
+        .iter_mut()
+        .filter(|listener| listener.protocol == Protocol::Tcp && !is_known_non_http(listener))
+        .filter_map(|listener| Some((probe_target(&listener.endpoints)?, listener)))
+        .collect();
+
+    run_bounded(targets, |(target, listener)| {
+        let Some((fingerprint, role)) = fingerprint_http(target) else {
+            return;
+        };
+        if let Some(role) = role {
+            match listener.role.evidence.kind {
+                EvidenceKind::UserRule => listener.role.add_alternative(role.candidate()),
+                EvidenceKind::Probe => listener.role.supersede(role),
+                _ => listener.role.consider(role),
+            }
+        }
+        listener.http = Some(fingerprint);
+    });
+}
+
+/// Whether a probe or the rule behind the current role says the listener does not
+/// speak HTTP, so a GET would only leave noise in the service's logs
+fn is_known_non_http(listener: &AggregatedListener) -> bool {
+    if let Some(probe) = &listener.probe {
+        return probe.protocol != "http";
+    }
+    let evidence = &listener.role.evidence;
+    let rule_is_non_http = |table: &RuleTable| {
+        table
+            .rules
+            .iter()
+            .any(|rule| rule.non_http && rule.command_pattern == evidence.matched)
+    };
+    match evidence.kind {
+        EvidenceKind::CommandRule => rule_is_non_http(&COMMAND_RULES),
+        EvidenceKind::CmdlineRule => rule_is_non_http(&CMDLINE_RULES),
+        EvidenceKind::PortRule => PORT_RULES
+            .iter()
+            .any(|&(port, _, _, non_http)| non_http && port.to_string() == evidence.matched),
+        _ => false,
+    }
+}
+
+/// Whether the server speaks first, as SSH, MySQL, SMTP and FTP do; HTTP servers never do
+fn greets_first(target: SocketAddr) -> bool {
+    let Ok(mut stream) = TcpStream::connect_timeout(&target, PROBE_CONNECT_TIMEOUT) else {
+        return false;
+    };
+    if stream
+        .set_read_timeout(Some(HTTP_GREETING_TIMEOUT))
+        .is_err()
+    {
+        return false;
+    }
+    matches!(stream.read(&mut [0; 1]), Ok(n) if n > 0)
+}
+
+/// Fetch `/` (following one same-host redirect) and match the response against HTTP_RULES
+///
+/// Servers that greet first are not HTTP and get no request.
+fn fingerprint_http(target: SocketAddr) -> Option<(HttpFingerprint, Option<Role>)> {
+    if greets_first(target) {
+        return None;
+    }
+    let first = http_get(target, "/")?;
+    let redirect = match first.status {
+        301 | 302 | 303 | 307 | 308 => first
+            .header("location")
+            .filter(|location| location.starts_with('/') && !location.starts_with("//"))
+            .map(str::to_string),
+        _ => None,
+    };
+    let response = match &redirect {
+        Some(location) => http_get(target, location).unwrap_or_else(|| first.clone()),
+        None => first.clone(),
+    };
+
+    // Headers like X-Powered-By may only be on the first response
+    let header = |name: &str| {
+        response
+            .header(name)
+            .or_else(|| first.header(name))
+            .map(str::to_string)
+    };
+    let fingerprint = HttpFingerprint {
+        status: response.status,
+        server: header("server"),
+        powered_by: header("x-powered-by"),
+        title: extract_html_title(&response.body),
+        redirect,
+    };
+    let role = infer_http_role(&response, &fingerprint);
+    Some((fingerprint, role))
+}
+
+/// Send `GET <path>` and read the response until the server closes or limits are hit
+fn http_get(target: SocketAddr, path: &str) -> Option<HttpResponse> {
+    let mut stream = TcpStream::connect_timeout(&target, PROBE_CONNECT_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(PROBE_READ_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(PROBE_READ_TIMEOUT)).ok()?;
+    let request = format!(
+        "GET {path} HTTP/1.0\r\nHost: localhost\r\nUser-Agent: whichport\r\nAccept: text/html,*/*\r\nConnection: close\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).ok()?;
+
+    let mut raw = Vec::new();
+    let mut buf = [0; 8192];
+    let deadline = std::time::Instant::now() + HTTP_REQUEST_TIMEOUT;
+    while raw.len() < HTTP_MAX_RESPONSE {
+        // Each read is bounded too, so a trickling stream cannot outlive the deadline
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero()
+            || stream
+                .set_read_timeout(Some(remaining.min(PROBE_READ_TIMEOUT)))
+                .is_err()
+        {
+            break;
+        }
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => raw.extend_from_slice(&buf[..n]),
+        }
+    }
+    parse_http_response(&raw)
+}
+
+/// Parse a raw HTTP/1.x response into status, lowercased headers and body text
+fn parse_http_response(raw: &[u8]) -> Option<HttpResponse> {
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .or_else(|| text.split_once("\n\n"))
+        .unwrap_or((&text, ""));
+    let mut lines = head.lines();
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1)?.parse().ok()?;
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+
+    Some(HttpResponse {
+        status,
+        headers,
+        body: body.to_string(),
+    })
+}
+
+/// Extract the text of the first `<title>` element, with whitespace collapsed
+fn extract_html_title(body: &str) -> Option<String> {
+    let lower = body.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = body[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'");
+    (!title.is_empty()).then_some(title)
+}
+
+/// Infer a role from an HTTP response using HTTP_RULES
+fn infer_http_role(response: &HttpResponse, fingerprint: &HttpFingerprint) -> Option<Role> {
+    let body = response.body.to_ascii_lowercase();
+    let title = fingerprint
+        .title
+        .as_deref()
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    HTTP_RULES
+        .iter()
+        .find(|rule| match rule.field {
+            HttpField::Header(name) => response
+                .header(name)
+                .is_some_and(|value| value.to_ascii_lowercase().contains(rule.pattern)),
+            HttpField::Title => title.contains(rule.pattern),
+            HttpField::Body => body.contains(rule.pattern),
+        })
+        .map(|rule| {
+            let matched = match rule.field {
+                HttpField::Header(name) => format!("{name}: {}", rule.pattern),
+                HttpField::Title => format!("title: {}", rule.pattern),
+                HttpField::Body => format!("body: {}", rule.pattern),
+            };
+            Role::new(
+                rule.description,
+                rule.confidence,
+                Evidence::new(EvidenceKind::Http, matched.trim_end_matches(": ")),
+            )
+        })
+}
+
+/// Fetch and attach the certificate of every TCP listener that answers a TLS ClientHello
+///
+/// Returns warnings for TLS listeners whose certificate could not be read.
+fn inspect_tls_listeners(aggregated: &mut [AggregatedListener], now: u64) -> Vec<String> {
+    let client_hello = build_tls_client_hello();
+    let targets: Vec<_> = aggregated
+        .iter_mut()
+        .filter(|listener| listener.protocol == Protocol::Tcp)
+        .filter_map(|listener| Some((probe_target(&listener.endpoints)?, listener)))
+        .collect();
+
+    run_bounded(targets, |(target, listener)| {
+        let speaks_tls = probe_exchange(target, Some(&client_hello))
+            .and_then(|reply| parse_tls_reply(&reply))
+            .is_some();
+        if !speaks_tls {
+            return None;
+        }
+        match fetch_tls_certificate(target, now) {
+            Ok(certificate) => {
+                listener.tls = Some(certificate);
+                None
+            }
+            Err(err) => Some(format!("tls {target}: {err}")),
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Perform a handshake with `openssl s_client` and decode the leaf certificate
+fn fetch_tls_certificate(target: SocketAddr, now: u64) -> Result<TlsCertificate, String> {
+    let handshake = run_openssl(
+        &[
+            "s_client",
+            "-connect",
+            &target.to_string(),
+            "-servername",
+            "localhost",
+        ],
+        None,
+    )?;
+    let pem = extract_pem_certificate(&handshake).ok_or("no certificate in handshake")?;
+    let text = run_openssl(
+        &[
+            "x509", "-noout", "-nameopt", "RFC2253", "-subject", "-issuer", "-enddate", "-text",
+        ],
+        Some(pem.as_bytes()),
+    )?;
+    parse_x509_text(&text, now).ok_or_else(|| "unreadable certificate".to_string())
+}
+
+/// Run openssl with optional stdin, killing it after TLS_COMMAND_TIMEOUT
+fn run_openssl(args: &[&str], input: Option<&[u8]>) -> Result<String, String> {
+    use std::process::Stdio;
+
+    let stdin = match input {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    let mut child = Command::new("openssl")
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run openssl: {e}"))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input).map_err(|e| e.to_string())?;
+    }
+
+    let deadline = std::time::Instant::now() + TLS_COMMAND_TIMEOUT;
+    while child.try_wait().map_err(|e| e.to_string())?.is_none() {
+        if std::time::Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("openssl {} timed out", args[0]));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout
+            .read_to_string(&mut output)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(output)
+}
+
+/// Extract the first PEM certificate block from `openssl s_client` output
+fn extract_pem_certificate(output: &str) -> Option<String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let start = output.find(BEGIN)?;
+    let end = output[start..].find(END)? + start + END.len();
+    Some(format!("{}\n", &output[start..end]))
+}
+
+/// Parse `openssl x509 -subject -issuer -enddate -text` output
+fn parse_x509_text(text: &str, now: u64) -> Option<TlsCertificate> {
+    let field = |prefix: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .map(|value| value.trim().to_string())
+    };
+    let subject = field("subject=")?;
+    let issuer = field("issuer=")?;
+    let (not_after, expires_at) = parse_openssl_date(&field("notAfter=")?)?;
+
+    let mut lines = text.lines();
+    let sans = lines
+        .find(|line| {
+            line.trim_start()
+                .starts_with("X509v3 Subject Alternative Name")
+        })
+        .and_then(|_| lines.next())
+        .map(|line| line.split(',').map(|san| san.trim().to_string()).collect())
+        .unwrap_or_default();
+
+    let seconds_left = expires_at as i64 - now as i64;
+    Some(TlsCertificate {
+        self_signed: subject == issuer,
+        subject,
+        issuer,
+        sans,
+        not_after,
+        days_until_expiry: seconds_left.div_euclid(86_400),
+        expired: seconds_left < 0,
+    })
+}
+
+/// Parse an openssl date like `Nov 15 19:41:49 2026 GMT` into (ISO 8601, epoch seconds)
+fn parse_openssl_date(raw: &str) -> Option<(String, u64)> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut fields = raw.split_whitespace();
+    let month_name = fields.next()?;
+    let month = MONTHS.iter().position(|&m| m == month_name)? as u32 + 1;
+    let day: u32 = fields.next()?.parse().ok()?;
+    let time = fields.next()?;
+    let year: i64 = fields.next()?.parse().ok()?;
+
+    let mut hms = time.split(':').map(|part| part.parse::<u32>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let epoch = days * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    let iso = format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z");
+    Some((iso, u64::try_from(epoch).ok()?))
+}
+
+/// Build a minimal TLS 1.2 ClientHello; any TLS server answers it with a handshake or alert
+fn build_tls_client_hello() -> Vec<u8> {
+    const CIPHER_SUITES: &[u16] = &[
+        0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0x009c, 0x009d,
+        0x002f, 0x0035,
+    ];
+    // supported_groups (x25519, secp256r1, secp384r1), ec_point_formats, signature_algorithms
+    const EXTENSIONS: &[u8] = &[
+        0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, //
+        0x00, 0x0b, 0x00, 0x02, 0x01, 0x00, //
+        0x00, 0x0d, 0x00, 0x0e, 0x00, 0x0c, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08,
+        0x05, 0x05, 0x01,
+    ];
+
+    let mut body = vec![0x03, 0x03];
+    body.extend((0..32u8).map(|i| i.wrapping_mul(37).wrapping_add(11)));
+    body.push(0); // empty session id
+    body.extend(((CIPHER_SUITES.len() * 2) as u16).to_be_bytes());
+    for suite in CIPHER_SUITES {
+        body.extend(suite.to_be_bytes());
+    }
+    body.extend([0x01, 0x00]); // null compression only
+    body.extend((EXTENSIONS.len() as u16).to_be_bytes());
+    body.extend(EXTENSIONS);
+
+    let mut handshake = vec![0x01];
+    handshake.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend(body);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend((handshake.len() as u16).to_be_bytes());
+    record.extend(handshake);
+    record
+}
+
+/// Grouping key for aggregation: (port, protocol, netns, pid, command, user, socket path)
+///
+/// Unix sockets are keyed by path too, so each socket keeps its own row and role.
+type ListenerKey = (
+    u16,
+    Protocol,
+    Option<String>,
+    Option<u32>,
+    String,
+    String,
+    Option<String>,
+);
+
+/// Infer the role of a Unix socket listener from its paths, then its command name
+fn infer_unix_role(paths: &[String], command: &str) -> Role {
+    for path in paths {
+        if let Some(rule) = most_specific_rule(&UNIX_PATH_RULES, &path.to_ascii_lowercase()) {
+            return rule.role(EvidenceKind::UnixPathRule);
+        }
+    }
+
+    // No port rules apply to Unix sockets; fall through to command rules
+    infer_role(0, Protocol::Unix, command)
+}
+
+/// Infer a role from the full command line and executable path, if any rule matches
+fn infer_cmdline_role(cmdline: Option<&str>, exe: Option<&str>) -> Option<Role> {
+    let haystack = format!("{} {}", cmdline.unwrap_or(""), exe.unwrap_or("")).to_ascii_lowercase();
+
+    most_specific_rule(&CMDLINE_RULES, &haystack).map(|rule| rule.role(EvidenceKind::CmdlineRule))
+}
+
+/// Replace name-based roles with command-line based ones where a cmdline rule matches
+/// with at least the same confidence; weaker hints are kept as alternatives
+fn refine_roles_from_cmdline(aggregated: &mut [AggregatedListener]) {
+    for listener in aggregated.iter_mut() {
+        if let Some(role) = infer_cmdline_role(listener.cmdline.as_deref(), listener.exe.as_deref())
+        {
+            listener.role.consider(role);
+        }
+    }
+}
+
+/// Aggregate listeners by (port, protocol, netns, pid, command, user) and merge endpoints
+fn aggregate_listeners(listeners: &[Listener]) -> Vec<AggregatedListener> {
+    let mut grouped: BTreeMap<ListenerKey, BTreeSet<String>> = BTreeMap::new();
+
+    for listener in listeners {
+        grouped
+            .entry((
+                listener.port,
+                listener.protocol,
+                listener.netns.clone(),
+                listener.pid,
+                listener.command.clone(),
+                listener.user.clone(),
+                (listener.protocol == Protocol::Unix).then(|| listener.endpoint.clone()),
+            ))
+            .or_default()
+            .insert(listener.endpoint.clone());
+    }
+
+    grouped
+        .into_iter()
+        .map(
+            |((port, protocol, netns, pid, command, user, _), endpoints)| {
+                let endpoints_vec: Vec<String> = endpoints.into_iter().collect();
+                let primary_endpoint = endpoints_vec.first().cloned().unwrap_or_default();
+                let role = match protocol {
+                    Protocol::Unix => infer_unix_role(&endpoints_vec, &command),
+                    _ => infer_role(port, protocol, &command),
+                };
+
+                AggregatedListener {
+                    port,
+                    protocol,
+                    pid,
+                    command,
+                    user,
+                    endpoint: primary_endpoint,
+                    endpoints: endpoints_vec,
+                    role,
+                    service: None,
+                    connections: None,
+                    netns,
+                    container: None,
+                    unit: None,
+                    socket_activation: None,
+                    probe: None,
+                    http: None,
+                    tls: None,
+                    cmdline: None,
+                    exe: None,
+                    cwd: None,
+                }
+            },
+        )
+        .collect()
+}
+
+/// Print results for specific ports in text format
+fn print_ports_text(
+    aggregated: &[AggregatedListener],
+    ports: &[u16],
+    source: &str,
+    timestamp: u64,
+    errors: &[String],
+    verbose: bool,
+    max_len: usize,
+) {
+    print_text_meta(source, timestamp, errors, verbose);
+
+    for &port in ports {
+        let matches: Vec<&AggregatedListener> =
+            aggregated.iter().filter(|l| l.port == port).collect();
+        if matches.is_empty() {
+            println!("port {port}: not listening");
+            continue;
+        }
+
+        for listener in matches {
+            print_listener_text(listener, verbose, max_len);
+        }
+    }
+}
+
+/// Print all listening ports in text format
+fn print_all_text(
+    aggregated: &[AggregatedListener],
+    source: &str,
+    timestamp: u64,
+    errors: &[String],
+    verbose: bool,
+    max_len: usize,
+) {
+    print_text_meta(source, timestamp, errors, verbose);
+
+    if aggregated.is_empty() {
+        println!("no listening ports found");
+        return;
+    }
+
+    for listener in aggregated {
+        print_listener_text(listener, verbose, max_len);
+    }
+}
+
+/// Print a single listener in text format; --verbose adds process detail lines
+fn print_listener_text(listener: &AggregatedListener, verbose: bool, max_len: usize) {
+    let endpoints = listener.endpoints.join(", ");
+    let mut label = match listener.protocol {
+        Protocol::Unix => "unix".to_string(),
+        protocol => format!("port {}/{}", protocol.as_str(), listener.port),
+    };
+    if let Some(netns) = &listener.netns {
+        label.push_str(&format!(" [netns {netns}]"));
+    }
+    let mut owner = format!("pid {}, user {}", pid_display(listener.pid), listener.user);
+    if let Some(container) = &listener.container {
+        owner.push_str(&format!(", {}", container_display(container)));
+    }
+    if let Some(unit) = &listener.unit {
+        owner.push_str(&format!(", unit {unit}"));
+    }
+    if let Some(activation) = &listener.socket_activation {
+        owner.push_str(&format!(
+            ", socket {} -> {}",
+            activation.socket, activation.service
+        ));
+    }
+    let banner = listener
+        .probe
+        .as_ref()
+        .and_then(|probe| probe.banner.as_deref())
+        .map(|banner| format!(" [{banner}]"))
+        .unwrap_or_default();
+    println!(
+        "{}: {} ({}) on [{}] | {} ({}){}",
+        label,
+        listener.command,
+        owner,
+        endpoints,
+        listener.role.description,
+        listener.role.confidence.as_str(),
+        banner
+    );
+
+    if let Some(http) = &listener.http {
+        println!("{}", build_http_line(http));
+    }
+
+    if let Some(certificate) = &listener.tls {
+        println!("{}", build_tls_line(certificate));
+    }
+
+    if verbose {
+        if let Some(service) = &listener.service {
+            println!("  service: {service}");
+        }
+        for line in build_role_lines(&listener.role) {
+            println!("{line}");
+        }
+        for line in build_process_detail_lines(listener, max_len) {
+            println!("{line}");
+        }
+    }
+
+    if let Some(groups) = &listener.connections {
+        for line in build_connection_lines(groups) {
+            println!("{line}");
+        }
+    }
+}
+
+/// Build the indented response summary line for --http text output
+fn build_http_line(http: &HttpFingerprint) -> String {
+    let mut line = format!("  http: {}", http.status);
+    if let Some(redirect) = &http.redirect {
+        line.push_str(&format!(" via {redirect}"));
+    }
+    if let Some(title) = &http.title {
+        line.push_str(&format!(", title \"{title}\""));
+    }
+    if let Some(server) = &http.server {
+        line.push_str(&format!(", server {server}"));
+    }
+    if let Some(powered_by) = &http.powered_by {
+        line.push_str(&format!(", x-powered-by {powered_by}"));
+    }
+    line
+}
+
+/// Build the indented certificate summary line for --tls text output
+fn build_tls_line(certificate: &TlsCertificate) -> String {
+    let mut line = format!(
+        "  tls: subject {}, issuer {}, san [{}], expires {} ({} days)",
+        certificate.subject,
+        certificate.issuer,
+        certificate.sans.join(", "),
+        certificate.not_after,
+        certificate.days_until_expiry
+    );
+    if certificate.expired {
+        line.push_str(" [EXPIRED]");
+    }
+    if certificate.self_signed {
+        line.push_str(" [self-signed]");
+    }
+    line
+}
+
+/// Build indented cmdline/exe/cwd lines for --verbose text output
+fn build_process_detail_lines(listener: &AggregatedListener, max_len: usize) -> Vec<String> {
+    [
+        ("cmdline", &listener.cmdline),
+        ("exe", &listener.exe),
+        ("cwd", &listener.cwd),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = value.as_deref()?;
+        Some(format!("  {name}: {}", truncate_text(value, max_len)))
+    })
+    .collect()
+}
+
+/// Build the indented evidence and alternative-role lines for verbose text output
+fn build_role_lines(role: &Role) -> Vec<String> {
+    let evidence = |evidence: &Evidence| match evidence.matched.as_str() {
+        "" => evidence.kind.as_str().to_string(),
+        matched => format!("{} \"{matched}\"", evidence.kind.as_str()),
+    };
+    let mut lines = vec![format!("  evidence: {}", evidence(&role.evidence))];
+    lines.extend(role.alternatives.iter().map(|alternative| {
+        format!(
+            "  alternative: {} ({}, {})",
+            alternative.description,
+            alternative.confidence.as_str(),
+            evidence(&alternative.evidence)
+        )
+    }));
+    lines
+}
+
+/// Truncate text to at most `max_len` characters, marking the cut with "..."; 0 disables
+///
+/// Limits shorter than the marker cut without it, so the result never exceeds `max_len`.
+fn truncate_text(text: &str, max_len: usize) -> String {
+    if max_len == 0 || text.chars().count() <= max_len {
+        return text.to_string();
+    }
+    if max_len < 3 {
+        return text.chars().take(max_len).collect();
+    }
+    let keep = max_len - 3;
+    let mut truncated: String = text.chars().take(keep).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+/// Short container label for text output, e.g. `docker 0123456789ab`
+fn container_display(container: &ContainerInfo) -> String {
+    let short_id = &container.id[..container.id.len().min(12)];
+    match &container.pod_uid {
+        Some(pod_uid) => format!("{} {short_id}, pod {pod_uid}", container.runtime),
+        None => format!("{} {short_id}", container.runtime),
+    }
+}
+
+/// Build indented text lines describing a listener's established connections
+fn build_connection_lines(groups: &[ConnectionGroup]) -> Vec<String> {
+    if groups.is_empty() {
+        return vec!["  <- no established connections".to_string()];
+    }
+
+    groups
+        .iter()
+        .map(|group| {
+            let peers: Vec<String> = group
+                .peers
+                .iter()
+                .map(|peer| match (&peer.command, peer.pid) {
+                    (Some(command), pid) => {
+                        format!("{} ({command}, pid {})", peer.endpoint, pid_display(pid))
+                    }
+                    (None, _) => peer.endpoint.clone(),
+                })
+                .collect();
+            format!(
+                "  <- {} x{}: {}",
+                group.address,
+                group.count,
+                peers.join(", ")
+            )
+        })
+        .collect()
+}
+
+/// Print listeners as a table or CSV/TSV rows per --format, --columns and --per-endpoint
+fn print_rows(aggregated: &[AggregatedListener], ports: Option<&[u16]>, cli: &Cli) {
+    let columns = select_columns(cli.columns.as_deref(), cli.format);
+    let rows = build_rows(aggregated, ports, &columns, cli.per_endpoint);
+
+    let lines = match cli.format {
+        OutputFormat::Csv => build_delimited_lines(&columns, &rows, ','),
+        OutputFormat::Tsv => build_delimited_lines(&columns, &rows, '\t'),
+        _ if rows.is_empty() => vec!["no listening ports found".to_string()],
+        _ => {
+            let cells: Vec<Vec<String>> = rows
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|cell| cell.unwrap_or_else(|| "-".to_string()))
+                        .collect()
+                })
+                .collect();
+            build_table_lines(&columns, &cells, terminal_width())
+        }
+    };
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+/// Columns to print for --format table/csv/tsv
+///
+/// csv/tsv always carry `listening`, so a queried port without a listener is told apart from a
+/// listener whose selected fields are all empty.
+fn select_columns(selected: Option<&[Column]>, format: OutputFormat) -> Vec<Column> {
+    let mut columns = match (selected, format) {
+        (Some(columns), _) => columns.to_vec(),
+        (None, OutputFormat::Table) => DEFAULT_COLUMNS.to_vec(),
+        (None, _) => Column::value_variants().to_vec(),
+    };
+    if format != OutputFormat::Table && !columns.contains(&Column::Listening) {
+        columns.push(Column::Listening);
+    }
+    columns
+}
+
+/// Flatten listeners into rows of column values
+///
+/// With `ports`, rows follow the queried port order and ports without a listener get a
+/// "not listening" row. With `per_endpoint`, each endpoint gets its own row.
+fn build_rows(
+    aggregated: &[AggregatedListener],
+    ports: Option<&[u16]>,
+    columns: &[Column],
+    per_endpoint: bool,
+) -> Vec<Vec<Option<String>>> {
+    let listener_rows = |listener: &AggregatedListener| -> Vec<Vec<Option<String>>> {
+        let row = |endpoint: Option<&str>| {
+            columns
+                .iter()
+                .map(|column| column.value(listener, endpoint))
+                .collect()
+        };
+        if per_endpoint {
+            listener.endpoints.iter().map(|e| row(Some(e))).collect()
+        } else {
+            vec![row(None)]
+        }
+    };
+
+    match ports {
+        None => aggregated.iter().flat_map(listener_rows).collect(),
+        Some(ports) => ports
+            .iter()
+            .flat_map(|&port| {
+                let matches: Vec<Vec<Option<String>>> = aggregated
+                    .iter()
+                    .filter(|l| l.port == port)
+                    .flat_map(listener_rows)
+                    .collect();
+                if matches.is_empty() {
+                    vec![not_listening_row(port, columns)]
+                } else {
+                    matches
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Row for a queried port that has no listener
+fn not_listening_row(port: u16, columns: &[Column]) -> Vec<Option<String>> {
+    columns
+        .iter()
+        .map(|column| match column {
+            Column::Port => Some(port.to_string()),
+            Column::Role => Some("not listening".to_string()),
+            Column::Listening => Some("no".to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Build a lowercase header line and one line per row, separated by `separator`
+///
+/// CSV fields are quoted when they contain the separator, quotes or line breaks; TSV has no
+/// quoting, so tabs and line breaks inside fields become spaces. Missing values are empty.
+fn build_delimited_lines(
+    columns: &[Column],
+    rows: &[Vec<Option<String>>],
+    separator: char,
+) -> Vec<String> {
+    let field = |value: &str| -> String {
+        if separator == '\t' {
+            return value.replace(['\t', '\n', '\r'], " ");
+        }
+        if value.contains([separator, '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let join = |fields: Vec<String>| fields.join(&separator.to_string());
+
+    let header = join(
+        columns
+            .iter()
+            .map(|column| column.header().to_ascii_lowercase())
+            .collect(),
+    );
+    std::iter::once(header)
+        .chain(rows.iter().map(|row| {
+            join(
+                row.iter()
+                    .map(|cell| field(cell.as_deref().unwrap_or("")))
+                    .collect(),
+            )
+        }))
+        .collect()
+}
+
+/// Lay out a header and rows in aligned columns
+///
+/// When `width` is given, the widest shrinkable column is narrowed one character at a time
+/// (down to MIN_COLUMN_WIDTH) until the table fits, and cut cells end in "...".
+fn build_table_lines(
+    columns: &[Column],
+    rows: &[Vec<String>],
+    width: Option<usize>,
+) -> Vec<String> {
+    let mut widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([column.header().len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    if let Some(width) = width {
+        let gaps = COLUMN_GAP.len() * columns.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + gaps > width {
+            let narrowest = |i: usize| MIN_COLUMN_WIDTH.max(columns[i].header().len());
+            let Some(i) = (0..columns.len())
+                .filter(|&i| columns[i].shrinkable() && widths[i] > narrowest(i))
+                .max_by_key(|&i| widths[i])
+            else {
+                break;
+            };
+            widths[i] -= 1;
+        }
+    }
+
+    let header: Vec<String> = columns.iter().map(|c| c.header().to_string()).collect();
+    std::iter::once(&header)
+        .chain(rows)
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(columns)
+                .zip(&widths)
+                .map(|((cell, column), &width)| {
+                    let cell = truncate_text(cell, width);
+                    if column.right_aligned() {
+                        format!("{cell:>width$}")
+                    } else {
+                        format!("{cell:<width$}")
+                    }
+                })
+                .collect();
+            cells.join(COLUMN_GAP).trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Width to fit --format table into: $COLUMNS, else the terminal size; None when piped
+fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&columns: &usize| columns > 0)
+    {
+        return Some(columns);
+    }
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_size_columns()
+}
+
+#[cfg(target_os = "linux")]
+fn terminal_size_columns() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn terminal_size_columns() -> Option<usize> {
+    // `stty size` reports "rows cols" for the terminal on its stdin
+    let output = Command::new("stty")
+        .arg("size")
+        .stdin(std::process::Stdio::inherit())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Print metadata in text format if verbose is enabled
+fn print_text_meta(source: &str, timestamp: u64, errors: &[String], verbose: bool) {
+    if !verbose {
+        return;
+    }
+
+    for line in build_text_meta_lines(source, timestamp, errors) {
+        println!("{line}");
+    }
+}
+
+/// Build metadata lines for text output
+fn build_text_meta_lines(source: &str, timestamp: u64, errors: &[String]) -> Vec<String> {
+    let mut lines = Vec::with_capacity(3 + errors.len());
+    lines.push(format!("meta source: {source}"));
+    lines.push(format!("meta timestamp: {timestamp}"));
+    lines.push(format!("meta errors: {}", errors.len()));
+    for err in errors {
+        lines.push(format!("meta error: {err}"));
+    }
+    lines
+}
+
+/// Print the --json envelope; `ports` is set in ports mode
+fn print_json(
+    aggregated: &[AggregatedListener],
+    ports: Option<&[u16]>,
+    mode: QueryMode,
+    source: &str,
+    timestamp: u64,
+    errors: &[String],
+) {
+    let output = build_json_output(aggregated, ports, mode, source, timestamp, errors);
+
+    // Use serde_json for safe and correct JSON serialization
+    match serde_json::to_string(&output) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("error: failed to serialize JSON: {e}"),
+    }
+}
+
+/// Build the --json envelope; with `ports`, listeners follow the queried port order
+fn build_json_output<'a>(
+    aggregated: &'a [AggregatedListener],
+    ports: Option<&[u16]>,
+    mode: QueryMode,
+    source: &'a str,
+    timestamp: u64,
+    errors: &'a [String],
+) -> JsonOutput<'a> {
+    let (ports, listeners) = match ports {
+        None => (Vec::new(), aggregated.iter().collect()),
+        Some(ports) => {
+            let mut listeners = Vec::new();
+            let statuses = ports
+                .iter()
+                .map(|&port| {
+                    let before = listeners.len();
+                    listeners.extend(aggregated.iter().filter(|l| l.port == port));
+                    PortStatus {
+                        port,
+                        listening: listeners.len() > before,
+                    }
+                })
+                .collect();
+            (statuses, listeners)
+        }
+    };
+
+    JsonOutput {
+        schema_version: SCHEMA_VERSION,
+        mode,
+        source,
+        timestamp,
+        errors,
+        ports,
+        listeners,
+    }
+}
+
+/// Load the output template from --template or --template-file, if either is given
+fn load_template(
+    inline: Option<&str>,
+    path: Option<&Path>,
+) -> Result<Option<Template>, WhichportError> {
+    let source = match (inline, path) {
+        (Some(inline), _) => inline.to_string(),
+        (None, Some(path)) => {
+            let raw = std::fs::read_to_string(path).map_err(|e| WhichportError::ReadFailed {
+                path: path.display().to_string(),
+                details: e.to_string(),
+            })?;
+            // Editors end files with a newline; each rendered listener gets its own anyway
+            raw.strip_suffix('\n').unwrap_or(&raw).to_string()
+        }
+        (None, None) => return Ok(None),
+    };
+    Template::parse(&source)
+        .map(Some)
+        .map_err(WhichportError::TemplateFailed)
+}
+
+/// Split `role.description` into path segments, each a field in the published JSON Schema
+///
+/// Segments are looked up in the listener definition's `properties`, following `$ref` into
+/// nested objects; arrays and scalars have no fields.
+fn parse_template_path(name: &str, schema: &serde_json::Value) -> Result<Vec<String>, String> {
+    let path: Vec<String> = name.split('.').map(str::to_string).collect();
+    let valid = path.iter().all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+    if !valid {
+        return Err(format!("invalid field name '{name}'"));
+    }
+
+    let mut node = &schema["$defs"]["listener"];
+    for (depth, segment) in path.iter().enumerate() {
+        while let Some(target) = node["$ref"]
+            .as_str()
+            .and_then(|pointer| pointer.strip_prefix('#'))
+            .and_then(|pointer| schema.pointer(pointer))
+        {
+            node = target;
+        }
+        match node["properties"].get(segment.as_str()) {
+            Some(property) => node = property,
+            None => return Err(format!("unknown field '{}'", path[..=depth].join("."))),
+        }
+    }
+    Ok(path)
+}
+
+/// Render template nodes against a listener's JSON value
+fn render_template_nodes(nodes: &[TemplateNode], listener: &serde_json::Value, out: &mut String) {
+    let lookup = |path: &[String]| {
+        path.iter()
+            .try_fold(listener, |value, segment| value.get(segment.as_str()))
+    };
+    for node in nodes {
+        match node {
+            TemplateNode::Text(text) => out.push_str(text),
+            TemplateNode::Field(path) => {
+                if let Some(value) = lookup(path) {
+                    out.push_str(&template_value_text(value));
+                }
+            }
+            TemplateNode::Section {
+                path,
+                inverted,
+                body,
+            } => {
+                if lookup(path).is_some_and(template_value_is_set) != *inverted {
+                    render_template_nodes(body, listener, out);
+                }
+            }
+        }
+    }
+}
+
+/// Text for a field: arrays are joined with ", ", null renders as nothing
+fn template_value_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(template_value_text)
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
+
+/// Whether a `{?field}` section renders: null, false, "" and [] count as unset
+fn template_value_is_set(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        _ => true,
+    }
+}
+
+/// Print one rendered template line per listener; with `ports`, listeners follow the
+/// queried port order and ports without a listener print nothing
+fn print_templated(aggregated: &[AggregatedListener], ports: Option<&[u16]>, template: &Template) {
+    let listeners: Vec<&AggregatedListener> = match ports {
+        None => aggregated.iter().collect(),
+        Some(ports) => ports
+            .iter()
+            .flat_map(|&port| aggregated.iter().filter(move |l| l.port == port))
+            .collect(),
+    };
+    let mut stdout = std::io::stdout().lock();
+    for listener in listeners {
+        if writeln!(stdout, "{}", template.render(listener)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Print NDJSON records, one per line, for `jq -c` pipelines and log shippers
+fn print_ndjson(
+    aggregated: &[AggregatedListener],
+    ports: Option<&[u16]>,
+    mode: QueryMode,
+    source: &str,
+    timestamp: u64,
+    errors: &[String],
+) {
+    let records = build_ndjson_records(aggregated, ports, mode, source, timestamp, errors);
+    let mut stdout = std::io::stdout().lock();
+    for record in records {
+        match serde_json::to_string(&record) {
+            // Stop quietly if the reader went away (e.g. `| head`)
+            Ok(json) => {
+                if writeln!(stdout, "{json}").is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("error: failed to serialize JSON: {e}"),
+        }
+    }
+}
+
+/// Build the meta record followed by listener records; with `ports`, records follow the
+/// queried port order and ports without a listener get a `not_listening` record
+fn build_ndjson_records<'a>(
+    aggregated: &'a [AggregatedListener],
+    ports: Option<&[u16]>,
+    mode: QueryMode,
+    source: &'a str,
+    timestamp: u64,
+    errors: &'a [String],
+) -> Vec<NdjsonRecord<'a>> {
+    let mut records = vec![NdjsonRecord::Meta {
+        schema_version: SCHEMA_VERSION,
+        mode,
+        source,
+        timestamp,
+        errors,
+    }];
+    match ports {
+        None => records.extend(aggregated.iter().map(NdjsonRecord::Listener)),
+        Some(ports) => {
+            for &port in ports {
+                let before = records.len();
+                records.extend(
+                    aggregated
+                        .iter()
+                        .filter(|l| l.port == port)
+                        .map(NdjsonRecord::Listener),
+                );
+                if records.len() == before {
+                    records.push(NdjsonRecord::NotListening { port });
+                }
+            }
+        }
+    }
+    records
+}
+
+/// Get current Unix timestamp
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Display PID as string, "unknown" if None
+fn pid_display(pid: Option<u32>) -> String {
+    pid.map_or_else(|| "unknown".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_from_endpoint_ipv4() {
+        assert_eq!(parse_port_from_endpoint("*:8080"), Some(8080));
+    }
+
+    #[test]
+    fn test_parse_port_from_endpoint_ipv6() {
+        assert_eq!(parse_port_from_endpoint("[::1]:5432"), Some(5432));
+    }
+
+    #[test]
+    fn test_parse_port_from_endpoint_invalid() {
+        assert_eq!(parse_port_from_endpoint("localhost"), None);
+    }
+
+    #[test]
+    fn test_parse_ss_process_info_complete() {
+        let raw = "users:((\"postgres\",pid=1178,fd=7))";
+        let (pid, command) = parse_ss_process_info(raw);
+        assert_eq!(pid, Some(1178));
+        assert_eq!(command, "postgres");
+    }
+
+    #[test]
+    fn test_parse_ss_process_info_missing() {
+        let (pid, command) = parse_ss_process_info("");
+        assert_eq!(pid, None);
+        assert_eq!(command, "unknown");
+    }
+
+    #[test]
+    fn test_parse_ss_output_variants() {
+        let raw = concat!(
+            "LISTEN 0 128 *:22 *:*\n",
+            "LISTEN 0 4096 127.0.0.53%lo:53 0.0.0.0:* users:((\"systemd-resolve\",pid=728,fd=14))\n",
+            "LISTEN 0 511 [::]:443 [::]:* users:((\"nginx\",pid=1000,fd=7))\n"
+        );
+        let parsed = parse_ss_output(raw, Protocol::Tcp, &HashMap::new());
+
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed.iter().any(|v| v.port == 22 && v.pid.is_none()));
+        assert!(parsed
+            .iter()
+            .any(|v| v.port == 53 && v.pid == Some(728) && v.command == "systemd-resolve"));
+        assert!(parsed
+            .iter()
+            .any(|v| v.port == 443 && v.pid == Some(1000) && v.command == "nginx"));
+    }
+
+    #[test]
+    fn test_parse_proc_net_tcp_listen_only() {
+        let raw = concat!(
+            "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+            "   0: 0100007F:1538 00000000:0000 0A 00000000:00000000 00:00000000 00000000   999        0 4242 1 0000000000000000 100 0 0 10 0\n",
+            "   1: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1717 1 0000000000000000 100 0 0 10 0\n",
+            "   2: 0100007F:D9FA 0100007F:1538 01 00000000:00000000 02:00000F40 00000000   999        0 2764 2 0000000000000000 20 4 14 16 -1\n"
+        );
+        let parsed = parse_proc_net(raw, Protocol::Tcp);
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(
+            parsed[0],
+            KernelSocket {
+                port: 5432,
+                protocol: Protocol::Tcp,
+                endpoint: "127.0.0.1:5432".to_string(),
+                uid: Some(999),
+                inode: 4242,
+            }
+        );
+        assert_eq!(parsed[1].endpoint, "0.0.0.0:22");
+        assert_eq!(parsed[1].uid, Some(0));
+    }
+
+    #[test]
+    fn test_parse_proc_net_udp_unconnected_only() {
+        let raw = concat!(
+            "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n",
+            "  100: 3500007F:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 3131 2 0000000000000000 0\n",
+            "  200: 0100007F:C350 0100007F:0035 01 00000000:00000000 00:00000000 00000000  1000        0 3232 2 0000000000000000 0\n"
+        );
+        let parsed = parse_proc_net(raw, Protocol::Udp);
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].protocol, Protocol::Udp);
+        assert_eq!(parsed[0].endpoint, "127.0.0.53:53");
+    }
+
+    #[test]
+    fn test_parse_proc_net_unix_listening_only() {
+        let raw = concat!(
+            "Num       RefCount Protocol Flags    Type St Inode Path\n",
+            "0000000000000000: 00000002 00000000 00010000 0001 01 20001 /var/run/postgresql/.s.PGSQL.5432\n",
+            "0000000000000000: 00000002 00000000 00010000 0005 01 20002 @/containerd-shim/abc.sock\n",
+            "0000000000000000: 00000003 00000000 00000000 0001 03 20003 /run/docker.sock\n",
+            "0000000000000000: 00000002 00000000 00010000 0002 01 20004 /run/dgram.sock\n",
+            "0000000000000000: 00000002 00000000 00010000 0001 01 20005\n"
+        );
+        let parsed = parse_proc_net_unix(raw);
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].protocol, Protocol::Unix);
+        assert_eq!(parsed[0].endpoint, "/var/run/postgresql/.s.PGSQL.5432");
+        assert_eq!(parsed[0].inode, 20001);
+        assert_eq!(parsed[1].endpoint, "@/containerd-shim/abc.sock");
+    }
+
+    #[test]
+    fn test_parse_lsof_unix_output_named_only() {
+        let raw =
+            "p90\ncdockerd\nLroot\nf5\nn/var/run/docker.sock\nf6\nn->0x1234\nf7\nntype=STREAM\n";
+        let parsed = parse_lsof_unix_output(raw);
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].endpoint, "/var/run/docker.sock");
+        assert_eq!(parsed[0].protocol, Protocol::Unix);
+    }
+
+    #[test]
+    fn test_infer_unix_role_by_path() {
+        let paths = vec!["/tmp/.s.PGSQL.5432".to_string()];
+        assert_eq!(
+            infer_unix_role(&paths, "postgres").description,
+            "PostgreSQL database"
+        );
+
+        let paths = vec!["/run/app.sock".to_string()];
+        assert_eq!(
+            infer_unix_role(&paths, "nginx").description,
+            "Web server or reverse proxy"
+        );
+    }
+
+    #[test]
+    fn test_parse_proc_net_tcp6_addresses() {
+        let raw = concat!(
+            "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+            "   0: 00000000000000000000000001000000:1538 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000   999        0 5151 1 0000000000000000 100 0 0 10 0\n",
+            "   1: 00000000000000000000000000000000:01BB 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 6161 1 0000000000000000 100 0 0 10 0\n"
+        );
+        let parsed = parse_proc_net(raw, Protocol::Tcp);
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].endpoint, "[::1]:5432");
+        assert_eq!(parsed[1].endpoint, "[::]:443");
+        assert_eq!(parsed[1].port, 443);
+    }
+
+    #[test]
+    fn test_kernel_sockets_to_listeners_uses_uid() {
+        let sockets = vec![KernelSocket {
+            port: 22,
+            protocol: Protocol::Tcp,
+            endpoint: "0.0.0.0:22".to_string(),
+            uid: Some(0),
+            inode: 1717,
+        }];
+        let listeners = kernel_sockets_to_listeners(&sockets, &HashMap::new(), &HashMap::new());
+
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].pid, None);
+        assert_eq!(listeners[0].command, "unknown");
+        assert_eq!(listeners[0].user, "0");
+    }
+
+    #[test]
+    fn test_kernel_sockets_to_listeners_applies_owner() {
+        let sockets = vec![KernelSocket {
+            port: 5432,
+            protocol: Protocol::Tcp,
+            endpoint: "127.0.0.1:5432".to_string(),
+            uid: Some(999),
+            inode: 4242,
+        }];
+        let owners = HashMap::from([(
+            4242,
+            SocketOwner {
+                pid: 871,
+                command: "postgres".to_string(),
+                uid: Some(999),
+            },
+        )]);
+        let users = HashMap::from([(999, "postgres".to_string())]);
+        let listeners = kernel_sockets_to_listeners(&sockets, &owners, &users);
+
+        assert_eq!(listeners[0].pid, Some(871));
+        assert_eq!(listeners[0].command, "postgres");
+        assert_eq!(listeners[0].user, "postgres");
+    }
+
+    /// Build a recorded-style SOCK_DIAG_BY_FAMILY reply for one socket
+    fn inet_diag_fixture(family: u8, port: u16, addr: &[u8], uid: u32, inode: u32) -> Vec<u8> {
+        let mut payload = vec![0u8; INET_DIAG_MSG_LEN];
+        payload[0] = family;
+        payload[1] = 10; // TCP_LISTEN
+        payload[4..6].copy_from_slice(&port.to_be_bytes());
+        payload[8..8 + addr.len()].copy_from_slice(addr);
+        payload[64..68].copy_from_slice(&uid.to_ne_bytes());
+        payload[68..72].copy_from_slice(&inode.to_ne_bytes());
+
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&((NLMSG_HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        msg.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        msg.extend_from_slice(&0x2u16.to_ne_bytes()); // NLM_F_MULTI
+        msg.extend_from_slice(&1u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&payload);
+        msg
+    }
+
+    fn nlmsg_done_fixture() -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&20u32.to_ne_bytes());
+        msg.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+        msg.extend_from_slice(&0x2u16.to_ne_bytes());
+        msg.extend_from_slice(&1u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&0i32.to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_build_inet_diag_request_layout() {
+        let req = build_inet_diag_request(10, 6, 1 << 10, 7);
+
+        assert_eq!(req.len(), 72);
+        assert_eq!(read_ne_u32(&req, 0), 72);
+        assert_eq!(u16::from_ne_bytes([req[4], req[5]]), SOCK_DIAG_BY_FAMILY);
+        assert_eq!(u16::from_ne_bytes([req[6], req[7]]), 0x301);
+        assert_eq!(read_ne_u32(&req, 8), 7);
+        assert_eq!(&req[16..20], &[10, 6, 0, 0]);
+        assert_eq!(read_ne_u32(&req, 20), 1 << 10);
+    }
+
+    #[test]
+    fn test_parse_inet_diag_messages_ipv4_and_ipv6() {
+        let mut buf = inet_diag_fixture(2, 5432, &[127, 0, 0, 1], 999, 4242);
+        let mut v6 = [0u8; 16];
+        v6[15] = 1;
+        buf.extend(inet_diag_fixture(10, 443, &v6, 0, 6161));
+        buf.extend(nlmsg_done_fixture());
+
+        let batch = parse_inet_diag_messages(&buf, Protocol::Tcp).unwrap();
+        assert!(batch.done);
+        assert_eq!(
+            batch.sockets,
+            vec![
+                KernelSocket {
+                    port: 5432,
+                    protocol: Protocol::Tcp,
+                    endpoint: "127.0.0.1:5432".to_string(),
+                    uid: Some(999),
+                    inode: 4242,
+                },
+                KernelSocket {
+                    port: 443,
+                    protocol: Protocol::Tcp,
+                    endpoint: "[::1]:443".to_string(),
+                    uid: Some(0),
+                    inode: 6161,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_inet_diag_messages_error() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&36u32.to_ne_bytes());
+        buf.extend_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        buf.extend_from_slice(&0u16.to_ne_bytes());
+        buf.extend_from_slice(&1u32.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&(-13i32).to_ne_bytes());
+        buf.resize(36, 0);
+
+        assert!(parse_inet_diag_messages(&buf, Protocol::Tcp).is_err());
+    }
+
+    #[test]
+    fn test_parse_inet_diag_messages_truncated() {
+        let buf = inet_diag_fixture(2, 80, &[0, 0, 0, 0], 0, 1);
+        assert!(parse_inet_diag_messages(&buf[..40], Protocol::Tcp).is_err());
+    }
+
+    const CONTAINER_ID: &str = "3f4e5d6c7b8a99887766554433221100ffeeddccbbaa00112233445566778899";
+
+    #[test]
+    fn test_parse_cgroup_container_docker() {
+        let v2 = format!("0::/system.slice/docker-{CONTAINER_ID}.scope\n");
+        let v1 = format!("12:memory:/docker/{CONTAINER_ID}\n11:cpu:/docker/{CONTAINER_ID}\n");
+
+        for raw in [v2, v1] {
+            let container = parse_cgroup_container(&raw).unwrap();
+            assert_eq!(container.runtime, "docker");
+            assert_eq!(container.id, CONTAINER_ID);
+            assert_eq!(container.pod_uid, None);
+        }
+    }
+
+    #[test]
+    fn test_parse_cgroup_container_podman() {
+        let raw = format!("0::/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{CONTAINER_ID}.scope/container\n");
+        let container = parse_cgroup_container(&raw).unwrap();
+        assert_eq!(container.runtime, "podman");
+        assert_eq!(container.id, CONTAINER_ID);
+    }
+
+    #[test]
+    fn test_parse_cgroup_container_kubepods() {
+        let systemd = format!(
+            "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1a2b3c4d_0000_1111_2222_333344445555.slice/cri-containerd-{CONTAINER_ID}.scope\n"
+        );
+        let container = parse_cgroup_container(&systemd).unwrap();
+        assert_eq!(container.runtime, "containerd");
+        assert_eq!(
+            container.pod_uid.as_deref(),
+            Some("1a2b3c4d-0000-1111-2222-333344445555")
+        );
+
+        let cgroupfs = format!(
+            "4:pids:/kubepods/besteffort/pod1a2b3c4d-0000-1111-2222-333344445555/{CONTAINER_ID}\n"
+        );
+        let container = parse_cgroup_container(&cgroupfs).unwrap();
+        assert_eq!(container.runtime, "kubernetes");
+        assert_eq!(container.id, CONTAINER_ID);
+        assert_eq!(
+            container.pod_uid.as_deref(),
+            Some("1a2b3c4d-0000-1111-2222-333344445555")
+        );
+    }
+
+    #[test]
+    fn test_parse_cgroup_container_host_process() {
+        assert_eq!(
+            parse_cgroup_container("0::/system.slice/nginx.service\n"),
+            None
+        );
+        assert_eq!(
+            parse_cgroup_container("0::/system.slice/containerd.service\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_docker_proxy_target() {
+        let cmdline = "/usr/bin/docker-proxy -proto tcp -host-ip 0.0.0.0 -host-port 8080 \
+                       -container-ip 172.17.0.2 -container-port 80";
+        assert_eq!(
+            parse_docker_proxy_target(cmdline),
+            Some(IpAddr::from([172, 17, 0, 2]))
+        );
+        assert_eq!(
+            parse_docker_proxy_target("docker-proxy --container-ip=fd00::2 --container-port=80"),
+            "fd00::2".parse().ok()
+        );
+        assert_eq!(parse_docker_proxy_target("docker-proxy -proto tcp"), None);
+    }
+
+    #[test]
+    fn test_netns_has_address() {
+        let fib_trie = "\
+Main:
+  +-- 0.0.0.0/0 3 0 5
+     |-- 0.0.0.0
+        /0 universe UNICAST
+     +-- 172.17.0.0/16 2 0 2
+        |-- 172.17.0.0
+           /16 link UNICAST
+        |-- 172.17.0.2
+           /32 host LOCAL
+        |-- 172.17.255.255
+           /32 link BROADCAST
+";
+        assert!(netns_has_address(fib_trie, IpAddr::from([172, 17, 0, 2])));
+        assert!(!netns_has_address(fib_trie, IpAddr::from([172, 17, 0, 0])));
+        assert!(!netns_has_address(fib_trie, IpAddr::from([172, 17, 0, 3])));
+
+        let if_inet6 = "fd000000000000000000000000000002 02 40 00 80     eth0\n\
+                        00000000000000000000000000000001 01 80 10 80       lo\n";
+        assert!(netns_has_address(if_inet6, "fd00::2".parse().unwrap()));
+        assert!(!netns_has_address(if_inet6, "fd00::3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_cgroup_unit() {
+        assert_eq!(
+            parse_cgroup_unit("0::/system.slice/nginx.service\n").as_deref(),
+            Some("nginx.service")
+        );
+        assert_eq!(
+            parse_cgroup_unit(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/vite.service\n"
+            )
+            .as_deref(),
+            Some("vite.service")
+        );
+        assert_eq!(
+            parse_cgroup_unit("0::/user.slice/user-1000.slice/user@1000.service/init.scope\n")
+                .as_deref(),
+            Some("user@1000.service")
+        );
+    }
+
+    #[test]
+    fn test_parse_cgroup_unit_v1_uses_systemd_hierarchy() {
+        let raw = "4:memory:/user.slice\n1:name=systemd:/system.slice/sshd.service\n";
+        assert_eq!(parse_cgroup_unit(raw).as_deref(), Some("sshd.service"));
+    }
+
+    #[test]
+    fn test_parse_cgroup_unit_outside_services() {
+        assert_eq!(
+            parse_cgroup_unit("0::/user.slice/user-1000.slice/session-3.scope\n"),
+            None
+        );
+        let raw = format!("0::/system.slice/docker-{CONTAINER_ID}.scope\n");
+        assert_eq!(parse_cgroup_unit(&raw), None);
+    }
+
+    #[test]
+    fn test_parse_proc_cmdline() {
+        assert_eq!(
+            parse_proc_cmdline(b"java\0-jar\0kafka.jar\0").as_deref(),
+            Some("java -jar kafka.jar")
+        );
+        assert_eq!(parse_proc_cmdline(b""), None);
+    }
+
+    #[test]
+    fn test_parse_ps_commands() {
+        let raw =
+            "  871 /usr/local/bin/postgres -D /usr/local/var/postgres\n 4410 node server.js\n";
+        assert_eq!(
+            parse_ps_commands(raw),
+            vec![
+                (
+                    871,
+                    "/usr/local/bin/postgres -D /usr/local/var/postgres".to_string()
+                ),
+                (4410, "node server.js".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_lsof_process_paths() {
+        let raw = "p871\nfcwd\nn/usr/local/var/postgres\nftxt\nn/usr/local/bin/postgres\nftxt\nn/usr/lib/dyld\np4410\nfcwd\nn/Users/dev/app\n";
+        let paths = parse_lsof_process_paths(raw);
+
+        assert_eq!(
+            paths[&871],
+            (
+                Some("/usr/local/var/postgres".to_string()),
+                Some("/usr/local/bin/postgres".to_string())
+            )
+        );
+        assert_eq!(paths[&4410], (Some("/Users/dev/app".to_string()), None));
+    }
+
+    #[test]
+    fn test_build_process_detail_lines() {
+        let mut listener = tcp_listener(5432, &["127.0.0.1:5432"]);
+        listener.cmdline =
+            Some("/usr/lib/postgresql/16/bin/postgres -D /var/lib/postgresql".to_string());
+        listener.exe = Some("/usr/lib/postgresql/16/bin/postgres".to_string());
+
+        assert_eq!(
+            build_process_detail_lines(&listener, 0),
+            vec![
+                "  cmdline: /usr/lib/postgresql/16/bin/postgres -D /var/lib/postgresql",
+                "  exe: /usr/lib/postgresql/16/bin/postgres",
+            ]
+        );
+        assert_eq!(
+            build_process_detail_lines(&listener, 20)[0],
+            "  cmdline: /usr/lib/postgres..."
+        );
+
+        // Limits below the "..." marker never produce longer output
+        assert_eq!(truncate_text("postgres", 1), "p");
+        assert_eq!(truncate_text("postgres", 2), "po");
+        assert_eq!(truncate_text("postgres", 3), "...");
+        assert_eq!(truncate_text("pg", 2), "pg");
+    }
+
+    #[test]
+    fn test_process_details_always_serialized() {
+        let json = serde_json::to_value(tcp_listener(5432, &["127.0.0.1:5432"])).unwrap();
+        assert!(json["cmdline"].is_null());
+        assert!(json["exe"].is_null());
+        assert!(json["cwd"].is_null());
+    }
+
+    #[test]
+    fn test_parse_socket_unit() {
+        let raw = "[Unit]\nDescription=CUPS Scheduler\n\n[Socket]\nListenStream=/run/cups/cups.sock\nListenStream=[::1]:631\n# ListenStream=0.0.0.0:632\nListenDatagram=0.0.0.0:631\n\n[Install]\nWantedBy=sockets.target\n";
+        let unit = parse_socket_unit("cups.socket", raw);
+
+        assert_eq!(unit.service, "cups.service");
+        assert_eq!(
+            unit.listens,
+            vec![
+                ListenAddress::Path("/run/cups/cups.sock".to_string()),
+                ListenAddress::Port(Protocol::Tcp, 631),
+                ListenAddress::Port(Protocol::Udp, 631),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_unit_service_name() {
+        let accept = parse_socket_unit("sshd.socket", "[Socket]\nListenStream=22\nAccept=yes\n");
+        assert_eq!(accept.service, "sshd@.service");
+        assert_eq!(accept.listens, vec![ListenAddress::Port(Protocol::Tcp, 22)]);
+
+        // Sequential packet sockets are Unix paths only, never TCP ports
+        let seqpacket = parse_socket_unit(
+            "lvm2-lvmpolld.socket",
+            "[Socket]\nListenSequentialPacket=/run/lvm/lvmpolld.socket\nListenSequentialPacket=9000\n",
+        );
+        assert_eq!(
+            seqpacket.listens,
+            vec![ListenAddress::Path("/run/lvm/lvmpolld.socket".to_string())]
+        );
+
+        let explicit = parse_socket_unit(
+            "docker.socket",
+            "[Socket]\nListenStream=/run/docker.sock\nService=docker.service\n",
+        );
+        assert_eq!(explicit.service, "docker.service");
+
+        let reset = parse_socket_unit(
+            "x.socket",
+            "[Socket]\nListenStream=80\nListenStream=\nListenStream=8080\n",
+        );
+        assert_eq!(
+            reset.listens,
+            vec![ListenAddress::Port(Protocol::Tcp, 8080)]
+        );
+    }
+
+    #[test]
+    fn test_held_by_system_manager_is_pid_1_only() {
+        let mut listener = tcp_listener(631, &["[::1]:631"]);
+        listener.command = "systemd".to_string();
+        listener.pid = Some(1);
+        assert!(held_by_system_manager(&listener));
+
+        // A `systemd --user` manager owns user-level sockets
+        listener.pid = Some(1834);
+        assert!(!held_by_system_manager(&listener));
+    }
+
+    #[test]
+    fn test_find_socket_unit() {
+        let units = vec![
+            parse_socket_unit("cups.socket", "[Socket]\nListenStream=631\n"),
+            parse_socket_unit("docker.socket", "[Socket]\nListenStream=/run/docker.sock\n"),
+        ];
+
+        let tcp = tcp_listener(631, &["[::1]:631"]);
+        assert_eq!(find_socket_unit(&units, &tcp).unwrap().name, "cups.socket");
+
+        let mut unix = tcp_listener(0, &["/run/docker.sock"]);
+        unix.protocol = Protocol::Unix;
+        assert_eq!(
+            find_socket_unit(&units, &unix).unwrap().name,
+            "docker.socket"
+        );
+
+        assert!(find_socket_unit(&units, &tcp_listener(632, &["[::1]:632"])).is_none());
+    }
+
+    #[test]
+    fn test_infer_cmdline_role() {
+        let cases = [
+            (
+                "java -Xmx1G -cp /opt/kafka/libs/* kafka.Kafka config/server.properties",
+                "Apache Kafka broker",
+                "high",
+            ),
+            (
+                "/usr/share/elasticsearch/jdk/bin/java -Des.path.home=/usr/share/elasticsearch org.elasticsearch.bootstrap.Elasticsearch",
+                "Elasticsearch search engine",
+                "high",
+            ),
+            (
+                "python manage.py runserver 0.0.0.0:8000",
+                "Django development server",
+                "high",
+            ),
+            ("python -m flask run --port 5000", "Flask development server", "high"),
+            (
+                "/venv/bin/python /venv/bin/uvicorn app.main:app --port 8000",
+                "ASGI application server (uvicorn)",
+                "high",
+            ),
+            (
+                "gunicorn: master [mysite.wsgi:application]",
+                "WSGI application server (gunicorn)",
+                "high",
+            ),
+            ("node /app/node_modules/.bin/vite --port 5173", "Vite development server", "high"),
+            (
+                "node /app/node_modules/.bin/webpack-dev-server --mode development",
+                "webpack dev server",
+                "high",
+            ),
+            (
+                "/usr/bin/python3 /usr/local/bin/jupyter-lab --no-browser",
+                "Jupyter notebook server",
+                "high",
+            ),
+        ];
+
+        for (cmdline, description, confidence) in cases {
+            let role = infer_cmdline_role(Some(cmdline), None).unwrap();
+            assert_eq!(role.description, description, "{cmdline}");
+            assert_eq!(role.confidence.as_str(), confidence, "{cmdline}");
+        }
+    }
+
+    #[test]
+    fn test_infer_cmdline_role_uses_exe_and_falls_through() {
+        let role =
+            infer_cmdline_role(Some("java -jar app.jar"), Some("/opt/kafka/jdk/bin/java")).unwrap();
+        assert_eq!(role.description, "Apache Kafka broker");
+        assert_eq!(role.confidence, Confidence::Medium);
+
+        assert!(infer_cmdline_role(Some("node server.js"), Some("/usr/bin/node")).is_none());
+        assert!(infer_cmdline_role(None, None).is_none());
+    }
+
+    #[test]
+    fn test_refine_roles_from_cmdline() {
+        let mut listeners = vec![tcp_listener(8000, &["0.0.0.0:8000"])];
+        listeners[0].cmdline = Some("python manage.py runserver".to_string());
+        refine_roles_from_cmdline(&mut listeners);
+        assert_eq!(listeners[0].role.description, "Django development server");
+
+        // Without a cmdline match the name-based role stays
+        let mut listeners = vec![tcp_listener(5432, &["0.0.0.0:5432"])];
+        refine_roles_from_cmdline(&mut listeners);
+        assert_eq!(listeners[0].role.description, "PostgreSQL database");
+
+        // A weak path hint must not override a high-confidence command role
+        let mut listeners = vec![tcp_listener(5432, &["0.0.0.0:5432"])];
+        listeners[0].cmdline = Some("postgres -D /srv/django/db".to_string());
+        refine_roles_from_cmdline(&mut listeners);
+        assert_eq!(listeners[0].role.description, "PostgreSQL database");
+        assert_eq!(listeners[0].role.confidence, Confidence::High);
+        assert_eq!(
+            listeners[0].role.alternatives[0].description,
+            "Django application server"
+        );
+
+        let mut listeners = vec![tcp_listener(6379, &["127.0.0.1:6379"])];
+        listeners[0].command = "redis-server".to_string();
+        listeners[0].role = infer_role(6379, Protocol::Tcp, "redis-server");
+        listeners[0].cmdline = Some("redis-server /srv/kafka-cache/redis.conf".to_string());
+        refine_roles_from_cmdline(&mut listeners);
+        assert_eq!(
+            listeners[0].role.description,
+            "Redis cache or message broker"
+        );
+        assert!(listeners[0]
+            .role
+            .alternatives
+            .iter()
+            .any(|alternative| alternative.description == "Apache Kafka broker"));
+    }
+
+    #[test]
+    fn test_parse_user_rules_toml() {
+        let raw = r#"
+[[rule]]
+command = "Billing"
+description = "Billing API"
+
+[[rule]]
+port_range = [9000, 9100]
+user = "svc-metrics"
+description = "Internal metrics exporter"
+confidence = "medium"
+
+[[rule]]
+cmdline = "java .*-jar /opt/ledger/.*\\.jar"
+description = "Ledger service"
+"#;
+        let rules = parse_user_rules(raw, false).unwrap();
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].command.as_ref().unwrap().pattern, "billing");
+        assert_eq!(rules[0].confidence, Confidence::High);
+        assert_eq!(rules[1].port_range, Some((9000, 9100)));
+        assert_eq!(rules[1].confidence, Confidence::Medium);
+        assert!(rules[2]
+            .cmdline
+            .as_ref()
+            .unwrap()
+            .is_match("java -Xmx1g -jar /opt/ledger/ledger-1.2.jar"));
+    }
+
+    #[test]
+    fn test_parse_user_rules_json() {
+        let raw =
+            r#"{"rules": [{"port": 7443, "description": "Admin console", "confidence": "low"}]}"#;
+        let rules = parse_user_rules(raw, true).unwrap();
+        assert_eq!(rules[0].port, Some(7443));
+        assert_eq!(rules[0].confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn test_parse_user_rules_rejects_invalid() {
+        let invalid = [
+            "[[rule]]\ndescription = \"No conditions\"\n",
+            "[[rule]]\nport_range = [9100, 9000]\ndescription = \"x\"\n",
+            "[[rule]]\ncmdline = \"(\"\ndescription = \"x\"\n",
+            "[[rule]]\nport = 1\ndescription = \"x\"\nconfidence = \"certain\"\n",
+            "[[rule]]\nprot = 1\ndescription = \"x\"\n",
+        ];
+        for raw in invalid {
+            assert!(parse_user_rules(raw, false).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_apply_user_rules_overrides_builtin() {
+        let rules = parse_user_rules(
+            "[[rule]]\nport = 5432\nuser = \"postgres\"\ndescription = \"Orders database\"\n\n[[rule]]\nport_range = [9000, 9100]\ndescription = \"Internal API\"\n",
+            false,
+        )
+        .unwrap();
+
+        let mut listeners = vec![
+            tcp_listener(5432, &["0.0.0.0:5432"]),
+            tcp_listener(9050, &["0.0.0.0:9050"]),
+            tcp_listener(9200, &["0.0.0.0:9200"]),
+        ];
+        listeners[0].user = "postgres".to_string();
+        apply_user_rules(&mut listeners, &rules);
+
+        assert_eq!(listeners[0].role.description, "Orders database");
+        assert_eq!(listeners[0].role.confidence, Confidence::High);
+        assert_eq!(listeners[1].role.description, "Internal API");
+        assert_eq!(listeners[2].role.description, "PostgreSQL database");
+    }
+
+    #[test]
+    fn test_load_user_rules_missing_explicit_path() {
+        let err = load_user_rules(Some(Path::new("/nonexistent/whichport/rules.toml")));
+        assert!(matches!(err, Err(WhichportError::RulesFailed { .. })));
+    }
+
+    #[test]
+    fn test_infer_role_node_is_exact() {
+        assert_eq!(
+            infer_role(3000, Protocol::Tcp, "node").description,
+            "Node.js application server"
+        );
+        assert_eq!(
+            infer_role(3000, Protocol::Tcp, "nodemon-helper").description,
+            "Unknown application service"
+        );
+        assert_eq!(
+            infer_role(9999, Protocol::Tcp, "mydockerthing").description,
+            "Unknown application service"
+        );
+    }
+
+    #[test]
+    fn test_most_specific_rule_prefers_narrow_match() {
+        static RULES: RuleTable = RuleTable::new(&[
+            RoleRule {
+                command_pattern: "api",
+                match_mode: MatchMode::Contains,
+                description: "Generic API",
+                confidence: Confidence::Medium,
+                non_http: false,
+            },
+            RoleRule {
+                command_pattern: "billing-api",
+                match_mode: MatchMode::Exact,
+                description: "Billing API",
+                confidence: Confidence::High,
+                non_http: false,
+            },
+            RoleRule {
+                command_pattern: "billing-*",
+                match_mode: MatchMode::Glob,
+                description: "Billing service",
+                confidence: Confidence::High,
+                non_http: false,
+            },
+        ]);
+
+        assert_eq!(
+            most_specific_rule(&RULES, "billing-api")
+                .unwrap()
+                .description,
+            "Billing API"
+        );
+        assert_eq!(
+            most_specific_rule(&RULES, "billing-worker")
+                .unwrap()
+                .description,
+            "Billing service"
+        );
+        assert_eq!(
+            most_specific_rule(&RULES, "search-api")
+                .unwrap()
+                .description,
+            "Generic API"
+        );
+        assert!(most_specific_rule(&RULES, "xbilling-worker").is_none());
+    }
+
+    #[test]
+    fn test_text_matcher_modes() {
+        let glob = TextMatcher::new(MatchMode::Glob, "java?*.jar").unwrap();
+        assert!(glob.is_match("java1-app.jar"));
+        assert!(!glob.is_match("run java1-app.jar"));
+
+        let regex = TextMatcher::new(MatchMode::Regex, r"^py(thon)?3?$").unwrap();
+        assert!(regex.is_match("python3"));
+        assert!(!regex.is_match("pypy"));
+
+        let prefix = TextMatcher::new(MatchMode::Prefix, "redis").unwrap();
+        assert!(prefix.is_match("redis-sentinel"));
+        assert!(!prefix.is_match("keydb-redis"));
+
+        assert!(TextMatcher::new(MatchMode::Regex, "(").is_err());
+    }
+
+    #[test]
+    fn test_builtin_rule_patterns_compile() {
+        for table in [&COMMAND_RULES, &CMDLINE_RULES, &UNIX_PATH_RULES] {
+            assert_eq!(table.compile().unwrap().len(), table.rules.len());
+        }
+
+        static BROKEN: RuleTable = RuleTable::new(&[RoleRule {
+            command_pattern: "(",
+            match_mode: MatchMode::Regex,
+            description: "Broken",
+            confidence: Confidence::Low,
+            non_http: false,
+        }]);
+        assert!(BROKEN.compile().is_err());
+    }
+
+    #[test]
+    fn test_apply_user_rules_resolves_by_priority_and_specificity() {
+        let rules = parse_user_rules(
+            r#"
+[[rule]]
+command = "post"
+description = "Any post* process"
+
+[[rule]]
+command = "postgres"
+command_match = "exact"
+description = "Exact postgres"
+
+[[rule]]
+port = 5432
+command = "post*"
+command_match = "glob"
+description = "Postgres on its default port"
+
+[[rule]]
+port = 6000
+description = "Low priority port rule"
+
+[[rule]]
+command = "postgres"
+priority = 10
+port = 6000
+description = "Pinned by priority"
+"#,
+            false,
+        )
+        .unwrap();
+
+        let mut listeners = vec![
+            tcp_listener(5432, &["0.0.0.0:5432"]),
+            tcp_listener(6000, &["0.0.0.0:6000"]),
+            tcp_listener(7000, &["0.0.0.0:7000"]),
+        ];
+        apply_user_rules(&mut listeners, &rules);
+
+        // Two conditions beat one; then exact beats contains
+        assert_eq!(
+            listeners[0].role.description,
+            "Postgres on its default port"
+        );
+        assert_eq!(listeners[1].role.description, "Pinned by priority");
+        assert_eq!(listeners[2].role.description, "Exact postgres");
+    }
+
+    /// Serve one canned exchange per connection on a loopback stub listener
+    ///
+    /// `respond` gets the bytes the client sent (empty if it sent nothing) and returns the
+    /// reply; `greeting` is written before reading, like SSH or MySQL servers do.
+    fn stub_listener(greeting: &'static [u8], respond: fn(&[u8]) -> Vec<u8>) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.write_all(greeting);
+                let mut buf = [0; 1024];
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(800)))
+                    .unwrap();
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let _ = stream.write_all(&respond(&buf[..n]));
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_probe_service_ssh_banner() {
+        let addr = stub_listener(b"SSH-2.0-OpenSSH_9.6\r\n", |_| Vec::new());
+        let result = probe_service(addr).unwrap();
+        assert_eq!(result.protocol, "ssh");
+        assert_eq!(result.banner.as_deref(), Some("SSH-2.0-OpenSSH_9.6"));
+    }
+
+    #[test]
+    fn test_probe_service_http() {
+        let addr = stub_listener(b"", |request| {
+            if request.starts_with(b"HEAD ") {
+                b"HTTP/1.1 200 OK\r\nServer: stub-httpd/1.0\r\n\r\n".to_vec()
+            } else {
+                Vec::new()
+            }
+        });
+        let result = probe_service(addr).unwrap();
+        assert_eq!(result.protocol, "http");
+        assert_eq!(result.banner.as_deref(), Some("stub-httpd/1.0"));
+    }
+
+    #[test]
+    fn test_probe_service_postgres_and_redis() {
+        let pg = stub_listener(b"", |request| {
+            if request == PG_SSL_REQUEST {
+                b"N".to_vec()
+            } else {
+                Vec::new()
+            }
+        });
+        assert_eq!(probe_service(pg).unwrap().protocol, "postgresql");
+
+        let redis = stub_listener(b"", |request| {
+            if request == REDIS_PING {
+                b"-NOAUTH Authentication required.\r\n".to_vec()
+            } else {
+                Vec::new()
+            }
+        });
+        let result = probe_service(redis).unwrap();
+        assert_eq!(result.protocol, "redis");
+        assert_eq!(
+            result.banner.as_deref(),
+            Some("-NOAUTH Authentication required.")
+        );
+    }
+
+    #[test]
+    fn test_probe_service_tls() {
+        let addr = stub_listener(b"", |request| {
+            if request.starts_with(&[0x16, 0x03]) {
+                // handshake_failure alert
+                vec![0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28]
+            } else {
+                Vec::new()
+            }
+        });
+        assert_eq!(probe_service(addr).unwrap().protocol, "tls");
+    }
+
+    #[test]
+    fn test_probe_service_silent_listener() {
+        let addr = stub_listener(b"", |_| Vec::new());
+        assert_eq!(probe_service(addr), None);
+    }
+
+    #[test]
+    fn test_parse_greeting_mysql() {
+        let mut greeting = vec![0x4a, 0x00, 0x00, 0x00, 0x0a];
+        greeting.extend(b"8.0.36\0");
+        greeting.extend([0x08, 0x00, 0x00, 0x00]);
+        let result = parse_greeting(&greeting).unwrap();
+        assert_eq!(result.protocol, "mysql");
+        assert_eq!(result.banner.as_deref(), Some("8.0.36"));
+    }
+
+    #[test]
+    fn test_build_tls_client_hello_lengths() {
+        let hello = build_tls_client_hello();
+        let record_len = u16::from_be_bytes([hello[3], hello[4]]) as usize;
+        assert_eq!(record_len, hello.len() - 5);
+        let handshake_len = u32::from_be_bytes([0, hello[6], hello[7], hello[8]]) as usize;
+        assert_eq!(handshake_len, hello.len() - 9);
+    }
+
+    #[test]
+    fn test_probe_target() {
+        let target = |endpoints: &[&str]| {
+            probe_target(&endpoints.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(target(&["0.0.0.0:80"]), "127.0.0.1:80".parse().ok());
+        assert_eq!(target(&["[::]:80"]), "[::1]:80".parse().ok());
+        assert_eq!(target(&["*:8080"]), "127.0.0.1:8080".parse().ok());
+        assert_eq!(target(&["10.0.0.5:22"]), "10.0.0.5:22".parse().ok());
+
+        // Interface-scoped endpoints as printed by ss
+        assert_eq!(target(&["127.0.0.53%lo:53"]), "127.0.0.53:53".parse().ok());
+        assert_eq!(
+            target(&["[fe80::1%2]:80"]),
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                80,
+                0,
+                2
+            )))
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            target(&["[fe80::1%lo]:80"]).map(|addr| match addr {
+                SocketAddr::V6(addr) => addr.scope_id(),
+                SocketAddr::V4(_) => 0,
+            }),
+            Some(1)
+        );
+        assert_eq!(target(&["[fe80::1%no-such-if0]:80"]), None);
+    }
+
+    #[test]
+    fn test_run_bounded_keeps_order_and_caps_workers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let results = run_bounded((0..40).collect(), |n: u32| {
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            active.fetch_sub(1, Ordering::SeqCst);
+            n * 2
+        });
+
+        assert_eq!(results, (0..40).map(|n| n * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= PROBE_WORKERS);
+        assert!(run_bounded(Vec::<u32>::new(), |n| n).is_empty());
+    }
+
+    #[test]
+    fn test_probe_unknown_listeners_upgrades_role() {
+        let addr = stub_listener(b"SSH-2.0-stub\r\n", |_| Vec::new());
+        let endpoint = addr.to_string();
+        let mut listener = tcp_listener(addr.port(), &[endpoint.as_str()]);
+        listener.role = infer_role(addr.port(), Protocol::Tcp, "myapp");
+        let mut listeners = vec![listener, tcp_listener(5432, &["127.0.0.1:1"])];
+
+        probe_unknown_listeners(&mut listeners);
+
+        assert_eq!(listeners[0].role.description, "SSH server");
+        assert_eq!(listeners[0].role.confidence, Confidence::Confirmed);
+        // Listeners with a known role are left alone
+        assert!(listeners[1].probe.is_none());
+    }
+
+    #[test]
+    fn test_infer_role_keeps_alternatives() {
+        let role = infer_role(22, Protocol::Tcp, "docker-proxy");
+
+        assert_eq!(role.description, "Docker published port proxy");
+        assert_eq!(
+            role.evidence,
+            Evidence::new(EvidenceKind::CommandRule, "docker-proxy")
+        );
+        let alternatives: Vec<_> = role
+            .alternatives
+            .iter()
+            .map(|a| (a.description.as_ref(), a.confidence, a.evidence.kind))
+            .collect();
+        assert_eq!(
+            alternatives,
+            vec![
+                (
+                    "Container runtime backend",
+                    Confidence::High,
+                    EvidenceKind::CommandRule
+                ),
+                ("SSH service", Confidence::Medium, EvidenceKind::PortRule),
+            ]
+        );
+
+        // Same description from command and port rules is listed once
+        assert!(infer_role(5432, Protocol::Tcp, "postgres")
+            .alternatives
+            .is_empty());
+
+        let unknown = infer_role(9999, Protocol::Tcp, "myapp");
+        assert_eq!(unknown.evidence.kind, EvidenceKind::Fallback);
+        assert!(unknown.alternatives.is_empty());
+    }
+
+    #[test]
+    fn test_role_supersede_ranks_alternatives() {
+        let mut role = infer_role(22, Protocol::Tcp, "nginx");
+        role.supersede(Role::new(
+            "Billing API",
+            Confidence::Low,
+            Evidence::new(EvidenceKind::UserRule, "rule 1"),
+        ));
+
+        assert_eq!(role.description, "Billing API");
+        assert_eq!(role.confidence, Confidence::Low);
+        let ranked: Vec<_> = role
+            .alternatives
+            .iter()
+            .map(|a| a.description.as_ref())
+            .collect();
+        assert_eq!(ranked, vec!["Web server or reverse proxy", "SSH service"]);
+        assert!(Confidence::Confirmed > Confidence::High && Confidence::Medium > Confidence::Low);
+
+        let mut unknown = Role::unknown();
+        unknown.supersede(role.clone());
+        assert_eq!(unknown.alternatives.len(), 2);
+
+        assert_eq!(
+            build_role_lines(&role),
+            vec![
+                "  evidence: user_rule \"rule 1\"",
+                "  alternative: Web server or reverse proxy (high, command_rule \"nginx\")",
+                "  alternative: SSH service (medium, port_rule \"22\")",
+            ]
+        );
+
+        // consider() only promotes candidates at least as confident as the current role
+        let mut hinted = infer_role(3000, Protocol::Tcp, "nginx");
+        hinted.consider(Role::new(
+            "Express.js application server",
+            Confidence::Medium,
+            Evidence::new(EvidenceKind::Http, "x-powered-by: express"),
+        ));
+        assert_eq!(hinted.description, "Web server or reverse proxy");
+        assert_eq!(
+            hinted.alternatives[0].description,
+            "Express.js application server"
+        );
+        hinted.consider(Role::new(
+            "Grafana dashboard",
+            Confidence::High,
+            Evidence::new(EvidenceKind::Http, "title: grafana"),
+        ));
+        assert_eq!(hinted.description, "Grafana dashboard");
+        assert!(hinted
+            .alternatives
+            .iter()
+            .all(|alternative| alternative.confidence <= hinted.confidence));
+
+        let mut registered = Role::unknown();
+        registered.consider(Role::new(
+            "kerberos (IANA registered)",
+            Confidence::Low,
+            Evidence::new(EvidenceKind::ServiceRegistry, "kerberos"),
+        ));
+        assert_eq!(registered.description, "kerberos (IANA registered)");
+        assert!(registered.alternatives.is_empty());
+    }
+
+    #[test]
+    fn test_role_json_includes_evidence_and_alternatives() {
+        let role = infer_role(22, Protocol::Tcp, "nginx");
+        let json = serde_json::to_value(&role).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "description": "Web server or reverse proxy",
+                "confidence": "high",
+                "evidence": {"kind": "command_rule", "matched": "nginx"},
+                "alternatives": [{
+                    "description": "SSH service",
+                    "confidence": "medium",
+                    "evidence": {"kind": "port_rule", "matched": "22"}
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_table_lines_aligns_columns() {
+        let mut postgres = tcp_listener(5432, &["127.0.0.1:5432", "[::1]:5432"]);
+        postgres.pid = Some(871);
+        postgres.command = "postgres".to_string();
+        postgres.user = "postgres".to_string();
+        let rows: Vec<Vec<String>> =
+            build_rows(&[postgres], Some(&[5432, 65535]), &DEFAULT_COLUMNS, false)
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|c| c.unwrap_or_else(|| "-".to_string()))
+                        .collect()
+                })
+                .collect();
+
+        assert_eq!(
+            build_table_lines(&DEFAULT_COLUMNS, &rows, None),
+            vec![
+                " PORT  PROTO  PID  USER      COMMAND   ADDRESS                     ROLE",
+                " 5432  tcp    871  postgres  postgres  127.0.0.1:5432, [::1]:5432  PostgreSQL database",
+                "65535  -        -  -         -         -                           not listening",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_table_lines_fits_width() {
+        let columns = [Column::Port, Column::Address, Column::Role];
+        let rows = vec![vec![
+            "8080".to_string(),
+            "0.0.0.0:8080, [::]:8080".to_string(),
+            "Unknown application service".to_string(),
+        ]];
+        let lines = build_table_lines(&columns, &rows, Some(40));
+
+        assert_eq!(
+            lines,
+            vec![
+                "PORT  ADDRESS           ROLE",
+                "8080  0.0.0.0:8080,...  Unknown appli...",
+            ]
+        );
+        assert!(lines.iter().all(|line| line.chars().count() <= 40));
+
+        // Columns never shrink below their minimum, even if the table still overflows
+        let narrow = build_table_lines(&columns, &rows, Some(10));
+        assert_eq!(narrow[1], "8080  0.0.0...  Unkno...");
+    }
+
+    #[test]
+    fn test_build_delimited_lines_csv_quoting() {
+        let mut listener = tcp_listener(8080, &["0.0.0.0:8080", "[::]:8080"]);
+        listener.command = "my \"app\"".to_string();
+        let columns = select_columns(
+            Some(&[
+                Column::Port,
+                Column::Pid,
+                Column::Command,
+                Column::Address,
+                Column::Unit,
+            ]),
+            OutputFormat::Csv,
+        );
+        let rows = build_rows(&[listener], Some(&[8080, 9]), &columns, false);
+
+        // Without `role` selected, `listening` still tells the unbound port apart
+        assert_eq!(
+            build_delimited_lines(&columns, &rows, ','),
+            vec![
+                "port,pid,command,address,unit,listening",
+                "8080,871,\"my \"\"app\"\"\",\"0.0.0.0:8080, [::]:8080\",,yes",
+                "9,,,,,no",
+            ]
+        );
+        assert_eq!(select_columns(None, OutputFormat::Table), DEFAULT_COLUMNS);
+    }
+
+    #[test]
+    fn test_build_delimited_lines_tsv_per_endpoint() {
+        let mut listener = tcp_listener(8080, &["0.0.0.0:8080", "[::]:8080"]);
+        listener.role.description = "tab\tinside".into();
+        let columns = [Column::Port, Column::Address, Column::Role];
+        let rows = build_rows(&[listener], None, &columns, true);
+
+        assert_eq!(
+            build_delimited_lines(&columns, &rows, '\t'),
+            vec![
+                "port\taddress\trole",
+                "8080\t0.0.0.0:8080\ttab inside",
+                "8080\t[::]:8080\ttab inside",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_ndjson_records() {
+        let listeners = vec![tcp_listener(5432, &["127.0.0.1:5432"])];
+        let errors = vec!["ss failed".to_string()];
+        let records = build_ndjson_records(
+            &listeners,
+            Some(&[65535, 5432]),
+            QueryMode::Ports,
+            "ss",
+            42,
+            &errors,
+        );
+        let lines: Vec<serde_json::Value> = records
+            .iter()
+            .map(|record| {
+                let line = serde_json::to_string(record).unwrap();
+                assert!(!line.contains('\n'));
+                serde_json::from_str(&line).unwrap()
+            })
+            .collect();
+
+        assert_eq!(
+            lines[0],
+            serde_json::json!({
+                "type": "meta",
+                "schemaVersion": 1,
+                "mode": "ports",
+                "source": "ss",
+                "timestamp": 42,
+                "errors": ["ss failed"]
+            })
+        );
+        assert_eq!(
+            lines[1],
+            serde_json::json!({"type": "not_listening", "port": 65535})
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({
+                "type": "listener",
+                "port": 5432,
+                "protocol": "tcp",
+                "pid": 871,
+                "command": "postgres",
+                "user": "postgres",
+                "endpoint": "127.0.0.1:5432",
+                "endpoints": ["127.0.0.1:5432"],
+                "role": {
+                    "description": "PostgreSQL database",
+                    "confidence": "high",
+                    "evidence": {"kind": "command_rule", "matched": "postgres"},
+                    "alternatives": []
+                },
+                "cmdline": null,
+                "exe": null,
+                "cwd": null
+            })
+        );
+        assert_eq!(lines.len(), 3);
+
+        let all = build_ndjson_records(&listeners, None, QueryMode::All, "ss", 42, &[]);
+        assert_eq!(all.len(), 2);
+    }
+
+    /// Listener with every optional field set, so shape tests see every key
+    fn fully_populated_listener() -> AggregatedListener {
+        let mut listener = tcp_listener(8443, &["0.0.0.0:8443"]);
+        listener.role.supersede(Role::new(
+            "Billing API",
+            Confidence::Low,
+            Evidence::new(EvidenceKind::UserRule, "rule 1"),
+        ));
+        listener.service = Some("pcsync-https".to_string());
+        listener.connections = Some(vec![ConnectionGroup {
+            address: "127.0.0.1".to_string(),
+            count: 1,
+            peers: vec![ConnectionPeer {
+                endpoint: "127.0.0.1:50000".to_string(),
+                pid: Some(4000),
+                command: Some("curl".to_string()),
+            }],
+        }]);
+        listener.netns = Some("blue".to_string());
+        listener.container = Some(ContainerInfo {
+            runtime: "kubernetes",
+            id: CONTAINER_ID.to_string(),
+            pod_uid: Some("8c2b0f58-6f7e-4c84-9a0b-3f1c2d4e5f60".to_string()),
+        });
+        listener.unit = Some("billing.service".to_string());
+        listener.socket_activation = Some(SocketActivation {
+            socket: "billing.socket".to_string(),
+            service: "billing.service".to_string(),
+        });
+        listener.probe = Some(ProbeResult {
+            protocol: "http",
+            banner: Some("nginx".to_string()),
+        });
+        listener.http = Some(HttpFingerprint {
+            status: 200,
+            server: Some("nginx".to_string()),
+            powered_by: Some("Express".to_string()),
+            title: Some("Billing".to_string()),
+            redirect: Some("/login".to_string()),
+        });
+        listener.tls = Some(TlsCertificate {
+            subject: "CN=localhost".to_string(),
+            issuer: "CN=localhost".to_string(),
+            sans: vec!["DNS:localhost".to_string()],
+            not_after: "2026-11-15T19:41:49Z".to_string(),
+            days_until_expiry: 29,
+            expired: false,
+            self_signed: true,
+        });
+        listener.cmdline = Some("/usr/bin/billing --port 8443".to_string());
+        listener.exe = Some("/usr/bin/billing".to_string());
+        listener.cwd = Some("/srv/billing".to_string());
+        listener
+    }
+
+    /// Minimal JSON Schema check covering the keywords used by schema/output.schema.json:
+    /// $ref, type, const, enum, properties, required, additionalProperties and items
+    ///
+    /// Unknown keys always fail, so `unevaluatedProperties: false` needs no handling;
+    /// `properties`/`required` next to a `$ref` extend the referenced schema.
+    fn assert_matches_schema(
+        root: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &serde_json::Value,
+        path: &str,
+    ) {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let mut merged = reference
+                .strip_prefix("#/")
+                .unwrap()
+                .split('/')
+                .fold(root, |target, segment| &target[segment])
+                .clone();
+            if let Some(extra) = schema["properties"].as_object() {
+                let properties = merged["properties"].as_object_mut().unwrap();
+                properties.extend(extra.clone());
+            }
+            if let Some(extra) = schema["required"].as_array() {
+                let required = merged["required"].as_array_mut().unwrap();
+                required.extend(extra.iter().cloned());
+            }
+            return assert_matches_schema(root, &merged, value, path);
+        }
+        if let Some(expected) = schema.get("const") {
+            assert_eq!(value, expected, "{path}");
+        }
+        if let Some(variants) = schema["enum"].as_array() {
+            assert!(
+                variants.contains(value),
+                "{path}: {value} not in {variants:?}"
+            );
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                serde_json::Value::String(t) => vec![t.as_str()],
+                other => other
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| t.as_str().unwrap())
+                    .collect(),
+            };
+            let actual = match value {
+                serde_json::Value::Null => "null",
+                serde_json::Value::Bool(_) => "boolean",
+                serde_json::Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+                serde_json::Value::Number(_) => "number",
+                serde_json::Value::String(_) => "string",
+                serde_json::Value::Array(_) => "array",
+                serde_json::Value::Object(_) => "object",
+            };
+            assert!(types.contains(&actual), "{path}: {actual} not in {types:?}");
+        }
+        if let Some(object) = value.as_object() {
+            let properties = schema["properties"].as_object().unwrap();
+            for key in schema["required"].as_array().into_iter().flatten() {
+                assert!(
+                    object.contains_key(key.as_str().unwrap()),
+                    "{path}: missing {key}"
+                );
+            }
+            for (key, field) in object {
+                let field_schema = properties
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{path}: {key} is not in the schema"));
+                assert_matches_schema(root, field_schema, field, &format!("{path}.{key}"));
+            }
+        }
+        if let Some(items) = value.as_array() {
+            for (i, item) in items.iter().enumerate() {
+                assert_matches_schema(root, &schema["items"], item, &format!("{path}[{i}]"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_output_shape_is_locked() {
+        let listeners = vec![tcp_listener(5432, &["127.0.0.1:5432"])];
+        let output = build_json_output(
+            &listeners,
+            Some(&[5432, 65535]),
+            QueryMode::Ports,
+            "ss",
+            42,
+            &[],
+        );
+
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "schemaVersion": 1,
+                "mode": "ports",
+                "source": "ss",
+                "timestamp": 42,
+                "errors": [],
+                "ports": [
+                    {"port": 5432, "listening": true},
+                    {"port": 65535, "listening": false}
+                ],
+                "listeners": [{
+                    "port": 5432,
+                    "protocol": "tcp",
+                    "pid": 871,
+                    "command": "postgres",
+                    "user": "postgres",
+                    "endpoint": "127.0.0.1:5432",
+                    "endpoints": ["127.0.0.1:5432"],
+                    "role": {
+                        "description": "PostgreSQL database",
+                        "confidence": "high",
+                        "evidence": {"kind": "command_rule", "matched": "postgres"},
+                        "alternatives": []
+                    },
+                    "cmdline": null,
+                    "exe": null,
+                    "cwd": null
+                }]
+            })
+        );
+
+        // Every mode uses the same envelope
+        let all = build_json_output(&listeners, None, QueryMode::Unix, "proc", 42, &[]);
+        let all = serde_json::to_value(&all).unwrap();
+        assert_eq!(all["mode"], "unix");
+        assert_eq!(all["ports"], serde_json::json!([]));
+        assert_eq!(all["listeners"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_json_output_matches_published_schema() {
+        let schema: serde_json::Value = serde_json::from_str(OUTPUT_SCHEMA).unwrap();
+        assert_eq!(
+            schema["properties"]["schemaVersion"]["const"],
+            SCHEMA_VERSION
+        );
+
+        let listeners = vec![
+            fully_populated_listener(),
+            tcp_listener(5432, &["[::1]:5432"]),
+        ];
+        let errors = vec!["ss failed".to_string()];
+        let output = build_json_output(
+            &listeners,
+            Some(&[8443, 5432, 9]),
+            QueryMode::Ports,
+            "netlink",
+            42,
+            &errors,
+        );
+        let value = serde_json::to_value(&output).unwrap();
+        assert_matches_schema(&schema, &schema, &value, "$");
+
+        // The schema documents no field the code never emits
+        let listener = &value["listeners"][0];
+        let emitted: BTreeSet<&str> = listener
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let documented: BTreeSet<&str> = schema["$defs"]["listener"]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(emitted, documented);
+
+        // Keys are camelCase throughout; snake_case is reserved for enum values
+        fn assert_camel_case_keys(value: &serde_json::Value) {
+            match value {
+                serde_json::Value::Object(object) => {
+                    for (key, field) in object {
+                        assert!(!key.contains('_'), "{key} is not camelCase");
+                        assert_camel_case_keys(field);
+                    }
+                }
+                serde_json::Value::Array(items) => items.iter().for_each(assert_camel_case_keys),
+                _ => {}
+            }
+        }
+        assert_camel_case_keys(&value);
+    }
+
+    #[test]
+    fn test_ndjson_records_match_published_schema() {
+        let schema: serde_json::Value = serde_json::from_str(OUTPUT_SCHEMA).unwrap();
+        let listeners = vec![fully_populated_listener()];
+        let errors = vec!["ss failed".to_string()];
+        let records = build_ndjson_records(
+            &listeners,
+            Some(&[8443, 9]),
+            QueryMode::Ports,
+            "netlink",
+            42,
+            &errors,
+        );
+
+        let mut kinds = Vec::new();
+        for record in &records {
+            let value = serde_json::to_value(record).unwrap();
+            let kind = value["type"].as_str().unwrap().to_string();
+            let definition = match kind.as_str() {
+                "meta" => "ndjsonMeta",
+                "listener" => "ndjsonListener",
+                "not_listening" => "ndjsonNotListening",
+                other => panic!("undocumented record type {other}"),
+            };
+            assert_matches_schema(&schema, &schema["$defs"][definition], &value, &kind);
+            kinds.push(kind);
+        }
+        assert_eq!(kinds, vec!["meta", "listener", "not_listening"]);
+    }
+
+    #[test]
+    fn test_template_renders_fields_and_sections() {
+        let mut listener = tcp_listener(5432, &["127.0.0.1:5432", "[::1]:5432"]);
+        let template = Template::parse(
+            "{port}/{protocol} {command}{?pid} (pid {pid}){/pid}{!pid} (pid ?){/pid} \
+             [{endpoints}] {role.description} {{{role.confidence}}}{?unit} {unit}{/unit}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&listener),
+            "5432/tcp postgres (pid 871) [127.0.0.1:5432, [::1]:5432] PostgreSQL database {high}"
+        );
+
+        listener.pid = None;
+        listener.unit = Some("postgresql.service".to_string());
+        assert_eq!(
+            template.render(&listener),
+            "5432/tcp postgres (pid ?) [127.0.0.1:5432, [::1]:5432] PostgreSQL database {high} postgresql.service"
+        );
+
+        // Nested sections and missing nested fields
+        let nested = Template::parse("{?container}{container.runtime}{?container.podUid} pod{/container.podUid}{/container}-").unwrap();
+        assert_eq!(nested.render(&listener), "-");
+    }
+
+    #[test]
+    fn test_template_parse_errors() {
+        let error = |source: &str| Template::parse(source).unwrap_err();
+
+        assert_eq!(error("{prot}"), "unknown field 'prot'");
+        assert_eq!(
+            error("{container.podUID}"),
+            "unknown field 'container.podUID'"
+        );
+        assert_eq!(
+            error("{?role}{role.descripton}{/role}"),
+            "unknown field 'role.descripton'"
+        );
+        assert_eq!(
+            error("{endpoints.first}"),
+            "unknown field 'endpoints.first'"
+        );
+        assert!(
+            Template::parse("{container.podUid} {role.evidence.kind} {http.poweredBy}").is_ok()
+        );
+        assert_eq!(error("{port"), "unclosed tag '{port'");
+        assert_eq!(
+            error("port}"),
+            "unmatched '}' (use '}}' for a literal brace)"
+        );
+        assert_eq!(error("{?pid}x"), "section 'pid' is never closed");
+        assert_eq!(error("{?pid}x{/port}"), "'{/port}' closes 'pid'");
+        assert_eq!(error("{/pid}"), "'{/pid}' closes no section");
+        assert_eq!(
+            error("{role..description}"),
+            "invalid field name 'role..description'"
+        );
+        assert_eq!(
+            Template::parse("{{port}}").unwrap().nodes,
+            vec![TemplateNode::Text("{port}".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_connections_conflicts_with_netns() {
+        assert!(Cli::try_parse_from(["whichport", "--all", "--connections"]).is_ok());
+        assert!(
+            Cli::try_parse_from(["whichport", "--all", "--connections", "--netns", "all"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_columns_option_parses_list() {
+        let cli = Cli::try_parse_from(["whichport", "--all", "--format", "table"]).unwrap();
+        assert_eq!(cli.columns, None);
+
+        let cli = Cli::try_parse_from([
+            "whichport",
+            "--all",
+            "--format",
+            "table",
+            "--columns",
+            "port,command,role,confidence",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.columns,
+            Some(vec![
+                Column::Port,
+                Column::Command,
+                Column::Role,
+                Column::Confidence
+            ])
+        );
+        assert!(
+            Cli::try_parse_from(["whichport", "--all", "--json", "--format", "table"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_services() {
+        let raw = "# Network services\n\
+                   ssh\t\t22/tcp\t\t\t\t# SSH Remote Login Protocol\n\
+                   kerberos\t88/tcp\t\tkerberos5 krb5\t# Kerberos v5\n\
+                   kerberos\t88/udp\t\tkerberos5 krb5\n\
+                   http-alt\t8080/tcp\twebcache\n\
+                   webcache\t8080/tcp\n\
+                   discard\t\t9/ddp\n\
+                   broken\n";
+        let services = parse_services(raw);
+
+        assert_eq!(services.len(), 4);
+        assert_eq!(services[&(Protocol::Tcp, 22)], "ssh");
+        assert_eq!(services[&(Protocol::Udp, 88)], "kerberos");
+        assert_eq!(services[&(Protocol::Tcp, 8080)], "http-alt");
+    }
+
+    #[test]
+    fn test_apply_service_names() {
+        let services = parse_services("kerberos 88/tcp\npostgresql 5432/tcp\n");
+        let mut aggregated = vec![
+            tcp_listener(88, &["0.0.0.0:88"]),
+            tcp_listener(5432, &["0.0.0.0:5432"]),
+            tcp_listener(9999, &["0.0.0.0:9999"]),
+        ];
+        aggregated[0].role = infer_role(88, Protocol::Tcp, "krb5kdc");
+        aggregated[2].role = infer_role(9999, Protocol::Tcp, "myapp");
+        apply_service_names(&mut aggregated, &services);
+
+        assert_eq!(aggregated[0].service.as_deref(), Some("kerberos"));
+        assert_eq!(aggregated[0].role.description, "kerberos (IANA registered)");
+        assert_eq!(aggregated[0].role.confidence, Confidence::Low);
+        assert_eq!(aggregated[1].service.as_deref(), Some("postgresql"));
+        assert_eq!(aggregated[1].role.description, "PostgreSQL database");
+        assert_eq!(aggregated[2].service, None);
+        assert_eq!(aggregated[2].role.description, UNKNOWN_ROLE);
+    }
+
+    #[test]
+    fn test_parse_http_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nServer: nginx/1.25.3\r\nX-Powered-By: Next.js\r\n\r\n<html><head><TITLE>\n  Shop &amp; Co\n</TITLE></head></html>";
+        let response = parse_http_response(raw).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("server"), Some("nginx/1.25.3"));
+        assert_eq!(response.header("x-powered-by"), Some("Next.js"));
+        assert_eq!(
+            extract_html_title(&response.body).as_deref(),
+            Some("Shop & Co")
+        );
+        assert!(parse_http_response(b"SSH-2.0-OpenSSH_9.6\r\n").is_none());
+    }
+
+    #[test]
+    fn test_fingerprint_http_vite() {
+        let addr = stub_listener(b"", |_| {
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html><head><script type=\"module\" src=\"/@vite/client\"></script><title>Storefront</title></head></html>".to_vec()
+        });
+        let (fingerprint, role) = fingerprint_http(addr).unwrap();
+
+        assert_eq!(fingerprint.status, 200);
+        assert_eq!(fingerprint.title.as_deref(), Some("Storefront"));
+        assert_eq!(role.unwrap().description, "Vite development server");
+        assert_eq!(
+            build_http_line(&fingerprint),
+            "  http: 200, title \"Storefront\""
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_http_follows_redirect() {
+        let addr = stub_listener(b"", |request| {
+            if request.starts_with(b"GET /login ") {
+                b"HTTP/1.1 200 OK\r\n\r\n<title>Grafana</title>".to_vec()
+            } else {
+                b"HTTP/1.1 302 Found\r\nLocation: /login\r\nX-Powered-By: Go\r\n\r\n".to_vec()
+            }
+        });
+        let (fingerprint, role) = fingerprint_http(addr).unwrap();
+
+        assert_eq!(fingerprint.status, 200);
+        assert_eq!(fingerprint.redirect.as_deref(), Some("/login"));
+        assert_eq!(fingerprint.powered_by.as_deref(), Some("Go"));
+        assert_eq!(role.unwrap().description, "Grafana dashboard");
+    }
+
+    #[test]
+    fn test_http_get_stops_streaming_responses_at_deadline() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n");
+            // One event every 100ms, for far longer than the request deadline
+            for _ in 0..100 {
+                if stream.write_all(b":\n").is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+
+        let started = std::time::Instant::now();
+        let response = http_get(addr, "/").unwrap();
+
+        assert_eq!(response.status, 200);
+        assert!(started.elapsed() < HTTP_REQUEST_TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_fingerprint_http_skips_servers_that_greet_first() {
+        // Answers anything, but greets first like an SSH or SMTP server
+        let addr = stub_listener(b"SSH-2.0-stub\r\n", |_| {
+            b"HTTP/1.1 200 OK\r\n\r\n<title>Grafana</title>".to_vec()
+        });
+        assert!(fingerprint_http(addr).is_none());
+    }
+
+    #[test]
+    fn test_fingerprint_http_listeners_skips_known_non_http() {
+        let addr = stub_listener(b"", |_| {
+            b"HTTP/1.1 200 OK\r\n\r\n<title>Grafana</title>".to_vec()
+        });
+        let endpoint = addr.to_string();
+        let mut listeners = vec![
+            // Non-HTTP command rule
+            tcp_listener(addr.port(), &[&endpoint]),
+            // Non-HTTP port rule
+            tcp_listener(addr.port(), &[&endpoint]),
+            // Probe found Redis
+            tcp_listener(addr.port(), &[&endpoint]),
+            // Role from a user rule
+            tcp_listener(addr.port(), &[&endpoint]),
+            // Probe only confirmed plain HTTP
+            tcp_listener(addr.port(), &[&endpoint]),
+            // Weak name-based role
+            tcp_listener(addr.port(), &[&endpoint]),
+        ];
+        listeners[1].command = "myapp".to_string();
+        listeners[1].role = infer_role(6379, Protocol::Tcp, "myapp");
+        listeners[2].role = Role::unknown();
+        listeners[2].probe = Some(ProbeResult {
+            protocol: "redis",
+            banner: None,
+        });
+        listeners[3].role = Role::new(
+            "Ops dashboard",
+            Confidence::Medium,
+            Evidence::new(EvidenceKind::UserRule, "rule 1"),
+        );
+        listeners[4].role = Role::new(
+            "HTTP server",
+            Confidence::Confirmed,
+            Evidence::new(EvidenceKind::Probe, "http"),
+        );
+        listeners[4].probe = Some(ProbeResult {
+            protocol: "http",
+            banner: None,
+        });
+        listeners[5].command = "node".to_string();
+        listeners[5].role = infer_role(addr.port(), Protocol::Tcp, "node");
+
+        fingerprint_http_listeners(&mut listeners);
+
+        assert!(listeners[..3]
+            .iter()
+            .all(|listener| listener.http.is_none()));
+        assert_eq!(listeners[0].role.description, "PostgreSQL database");
+        assert_eq!(
+            listeners[1].role.description,
+            "Redis cache or message broker"
+        );
+        assert_eq!(listeners[3].role.description, "Ops dashboard");
+        assert_eq!(
+            listeners[3].role.alternatives[0].description,
+            "Grafana dashboard"
+        );
+        assert_eq!(listeners[4].role.description, "Grafana dashboard");
+        assert_eq!(listeners[4].role.alternatives[0].description, "HTTP server");
+        assert_eq!(listeners[5].role.description, "Grafana dashboard");
+        assert!(listeners[3..]
+            .iter()
+            .all(|listener| listener.http.is_some()));
     }
 
-    grouped
-        .into_iter()
-        .map(
-            |((port, pid, command, user), endpoints)| {
-                let endpoints_vec: Vec<String> = endpoints.into_iter().collect();
-                let primary_endpoint = endpoints_vec.first().cloned().unwrap_or_default();
-                let role = infer_role(port, &command);
-
-                AggregatedListener {
-                    port,
-                    pid,
-                    command,
-                    user,
-                    endpoint: primary_endpoint,
-                    endpoints: endpoints_vec,
-                    role,
-                }
-            },
-        )
-        .collect()
-}
+    #[test]
+    fn test_infer_http_role_headers() {
+        let jenkins =
+            parse_http_response(b"HTTP/1.1 403 Forbidden\r\nX-Jenkins: 2.440\r\n\r\n").unwrap();
+        let fingerprint = HttpFingerprint {
+            status: 403,
+            server: None,
+            powered_by: None,
+            title: None,
+            redirect: None,
+        };
+        assert_eq!(
+            infer_http_role(&jenkins, &fingerprint).unwrap().description,
+            "Jenkins CI server"
+        );
 
-/// Print results for specific ports in text format
-fn print_ports_text(
-    listeners: &[Listener],
-    ports: &[u16],
-    source: &str,
-    timestamp: u64,
-    errors: &[String],
-    verbose: bool,
-) {
-    print_text_meta(source, timestamp, errors, verbose);
-    let aggregated = aggregate_listeners(listeners);
+        let plain = parse_http_response(b"HTTP/1.1 200 OK\r\nServer: Caddy\r\n\r\nhello").unwrap();
+        assert!(infer_http_role(&plain, &fingerprint).is_none());
+    }
 
-    for &port in ports {
-        let matches: Vec<&AggregatedListener> =
-            aggregated.iter().filter(|l| l.port == port).collect();
-        if matches.is_empty() {
-            println!("port {port}: not listening");
-            continue;
-        }
+    const X509_TEXT_FIXTURE: &str = "subject=CN=localhost
+issuer=CN=localhost
+notAfter=Nov 15 19:41:49 2026 GMT
+Certificate:
+    Data:
+        Version: 3 (0x2)
+        Issuer: CN=localhost
+        Validity
+            Not Before: Oct 16 19:41:49 2026 GMT
+            Not After : Nov 15 19:41:49 2026 GMT
+        Subject: CN=localhost
+        X509v3 extensions:
+            X509v3 Subject Alternative Name: \n                DNS:localhost, IP Address:127.0.0.1
+            X509v3 Basic Constraints: critical
+                CA:TRUE
+";
 
-        for listener in matches {
-            print_listener_text(listener);
-        }
+    #[test]
+    fn test_parse_openssl_date() {
+        assert_eq!(
+            parse_openssl_date("Nov 15 19:41:49 2026 GMT"),
+            Some(("2026-11-15T19:41:49Z".to_string(), 1_794_771_709))
+        );
+        assert_eq!(
+            parse_openssl_date("Jan  1 00:00:00 1970 GMT"),
+            Some(("1970-01-01T00:00:00Z".to_string(), 0))
+        );
+        assert_eq!(parse_openssl_date("Foo 1 00:00:00 2026 GMT"), None);
     }
-}
 
-/// Print all listening ports in text format
-fn print_all_text(
-    listeners: &[Listener],
-    source: &str,
-    timestamp: u64,
-    errors: &[String],
-    verbose: bool,
-) {
-    print_text_meta(source, timestamp, errors, verbose);
-    let aggregated = aggregate_listeners(listeners);
+    #[test]
+    fn test_parse_x509_text() {
+        // 2026-10-16T19:41:49Z, 30 days before expiry
+        let certificate = parse_x509_text(X509_TEXT_FIXTURE, 1_792_179_709).unwrap();
+        assert_eq!(certificate.subject, "CN=localhost");
+        assert_eq!(
+            certificate.sans,
+            vec!["DNS:localhost", "IP Address:127.0.0.1"]
+        );
+        assert_eq!(certificate.not_after, "2026-11-15T19:41:49Z");
+        assert_eq!(certificate.days_until_expiry, 30);
+        assert!(!certificate.expired);
+        assert!(certificate.self_signed);
 
-    if aggregated.is_empty() {
-        println!("no listening ports found");
-        return;
+        let expired = parse_x509_text(X509_TEXT_FIXTURE, 1_794_771_710).unwrap();
+        assert!(expired.expired);
+        assert_eq!(expired.days_until_expiry, -1);
+        assert_eq!(
+            build_tls_line(&expired),
+            "  tls: subject CN=localhost, issuer CN=localhost, san [DNS:localhost, IP Address:127.0.0.1], expires 2026-11-15T19:41:49Z (-1 days) [EXPIRED] [self-signed]"
+        );
     }
 
-    for listener in &aggregated {
-        print_listener_text(listener);
+    #[test]
+    fn test_extract_pem_certificate() {
+        let output = "CONNECTED(00000003)\n---\nServer certificate\n-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\nsubject=CN=localhost\n";
+        assert_eq!(
+            extract_pem_certificate(output).as_deref(),
+            Some("-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n")
+        );
+        assert_eq!(extract_pem_certificate("CONNECTED(00000003)\n"), None);
     }
-}
 
-/// Print a single listener in text format
-fn print_listener_text(listener: &AggregatedListener) {
-    let endpoints = listener.endpoints.join(", ");
-    println!(
-        "port {}: {} (pid {}, user {}) on [{}] | {} ({})",
-        listener.port,
-        listener.command,
-        pid_display(listener.pid),
-        listener.user,
-        endpoints,
-        listener.role.description,
-        listener.role.confidence
-    );
-}
+    #[test]
+    fn test_infer_role_docker_proxy() {
+        let role = infer_role(8080, Protocol::Tcp, "docker-proxy");
+        assert_eq!(role.description, "Docker published port proxy");
+        assert_eq!(
+            infer_role(2375, Protocol::Tcp, "dockerd").description,
+            "Container runtime backend"
+        );
+    }
 
-/// Print metadata in text format if verbose is enabled
-fn print_text_meta(source: &str, timestamp: u64, errors: &[String], verbose: bool) {
-    if !verbose {
-        return;
+    #[test]
+    fn test_container_display() {
+        let container = ContainerInfo {
+            runtime: "docker",
+            id: CONTAINER_ID.to_string(),
+            pod_uid: None,
+        };
+        assert_eq!(container_display(&container), "docker 3f4e5d6c7b8a");
     }
 
-    for line in build_text_meta_lines(source, timestamp, errors) {
-        println!("{line}");
+    #[test]
+    fn test_parse_netns_selector() {
+        assert_eq!(parse_netns_selector("all"), Ok(NetnsSelector::All));
+        assert_eq!(parse_netns_selector("1234"), Ok(NetnsSelector::Pid(1234)));
+        assert_eq!(
+            parse_netns_selector("cni-1"),
+            Ok(NetnsSelector::Name("cni-1".to_string()))
+        );
+        assert!(parse_netns_selector("../etc").is_err());
+        assert!(parse_netns_selector("").is_err());
     }
-}
 
-/// Build metadata lines for text output
-fn build_text_meta_lines(source: &str, timestamp: u64, errors: &[String]) -> Vec<String> {
-    let mut lines = Vec::with_capacity(3 + errors.len());
-    lines.push(format!("meta source: {source}"));
-    lines.push(format!("meta timestamp: {timestamp}"));
-    lines.push(format!("meta errors: {}", errors.len()));
-    for err in errors {
-        lines.push(format!("meta error: {err}"));
+    #[test]
+    fn test_parse_netns_link() {
+        assert_eq!(parse_netns_link("net:[4026531840]"), Some(4026531840));
+        assert_eq!(parse_netns_link("mnt:[4026531840]"), None);
     }
-    lines
-}
 
-/// Print all listening ports in JSON format
-fn print_all_json(listeners: &[Listener], source: &str, timestamp: u64, errors: &[String]) {
-    let aggregated = aggregate_listeners(listeners);
-    let output = AllPortsOutput {
-        mode: "all".to_string(),
-        source: source.to_string(),
-        timestamp,
-        errors: errors.to_vec(),
-        results: aggregated,
-    };
+    #[test]
+    fn test_select_namespaces() {
+        let discovered = vec![
+            NetNamespace {
+                inode: 4026531840,
+                name: None,
+                pid: Some(1),
+            },
+            NetNamespace {
+                inode: 4026532300,
+                name: Some("blue".to_string()),
+                pid: None,
+            },
+        ];
 
-    // Use serde_json for safe and correct JSON serialization
-    match serde_json::to_string(&output) {
-        Ok(json) => println!("{json}"),
-        Err(e) => eprintln!("error: failed to serialize JSON: {e}"),
-    }
-}
+        let all = select_namespaces(&discovered, &NetnsSelector::All, None).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].id(), "net:[4026531840]");
+        assert_eq!(all[1].id(), "blue");
 
-/// Print results for specific ports in JSON format
-fn print_ports_json(
-    listeners: &[Listener],
-    ports: &[u16],
-    source: &str,
-    timestamp: u64,
-    errors: &[String],
-) {
-    let aggregated = aggregate_listeners(listeners);
-    let results: Vec<PortResult> = ports
-        .iter()
-        .map(|&port| {
-            let matches: Vec<AggregatedListener> = aggregated
-                .iter()
-                .filter(|l| l.port == port)
-                .cloned()
-                .collect();
-            PortResult {
-                port,
-                listening: !matches.is_empty(),
-                listeners: matches,
-            }
-        })
-        .collect();
+        let named =
+            select_namespaces(&discovered, &NetnsSelector::Name("blue".to_string()), None).unwrap();
+        assert_eq!(named[0].inode, 4026532300);
 
-    let output = PortQueryOutput {
-        mode: "ports".to_string(),
-        source: source.to_string(),
-        timestamp,
-        errors: errors.to_vec(),
-        results,
-    };
+        let by_pid =
+            select_namespaces(&discovered, &NetnsSelector::Pid(42), Some(4026531840)).unwrap();
+        assert_eq!(by_pid[0].pid, Some(42));
 
-    match serde_json::to_string(&output) {
-        Ok(json) => println!("{json}"),
-        Err(e) => eprintln!("error: failed to serialize JSON: {e}"),
+        assert!(
+            select_namespaces(&discovered, &NetnsSelector::Name("red".to_string()), None).is_err()
+        );
     }
-}
 
-/// Get current Unix timestamp
-fn unix_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0)
-}
+    #[test]
+    fn test_aggregate_listeners_keeps_namespaces_apart() {
+        let listener = |netns: &str| Listener {
+            port: 80,
+            protocol: Protocol::Tcp,
+            pid: None,
+            command: "unknown".to_string(),
+            user: "root".to_string(),
+            endpoint: "0.0.0.0:80".to_string(),
+            netns: Some(netns.to_string()),
+        };
+        let aggregated = aggregate_listeners(&[listener("blue"), listener("red")]);
 
-/// Display PID as string, "unknown" if None
-fn pid_display(pid: Option<u32>) -> String {
-    pid.map_or_else(|| "unknown".to_string(), |v| v.to_string())
-}
+        assert_eq!(aggregated.len(), 2);
+        assert_eq!(aggregated[0].netns.as_deref(), Some("blue"));
+        assert_eq!(aggregated[1].netns.as_deref(), Some("red"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_parse_socket_inode_link() {
+        assert_eq!(parse_socket_inode_link("socket:[12345]"), Some(12345));
+        assert_eq!(parse_socket_inode_link("pipe:[12345]"), None);
+        assert_eq!(parse_socket_inode_link("/dev/null"), None);
+    }
 
     #[test]
-    fn test_parse_port_from_endpoint_ipv4() {
-        assert_eq!(parse_port_from_endpoint("*:8080"), Some(8080));
+    fn test_parse_status_uid_real_uid() {
+        let status =
+            "Name:\tpostgres\nUmask:\t0077\nUid:\t999\t999\t999\t999\nGid:\t999\t999\t999\t999\n";
+        assert_eq!(parse_status_uid(status), Some(999));
+        assert_eq!(parse_status_uid("Name:\tx\n"), None);
     }
 
+    #[cfg(target_os = "linux")]
     #[test]
-    fn test_parse_port_from_endpoint_ipv6() {
-        assert_eq!(parse_port_from_endpoint("[::1]:5432"), Some(5432));
+    fn test_resolve_socket_owners_finds_own_listener() {
+        let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let raw = std::fs::read_to_string("/proc/net/tcp").unwrap();
+        let inode = parse_proc_net(&raw, Protocol::Tcp)
+            .into_iter()
+            .find(|s| s.port == port)
+            .map(|s| s.inode)
+            .unwrap();
+
+        let (owners, _) = resolve_socket_owners(&HashSet::from([inode]));
+        assert_eq!(owners[&inode].pid, std::process::id());
     }
 
     #[test]
-    fn test_parse_port_from_endpoint_invalid() {
-        assert_eq!(parse_port_from_endpoint("localhost"), None);
+    fn test_parse_ss_output_extended_user() {
+        let raw = concat!(
+            "LISTEN 0 244 127.0.0.1:5432 0.0.0.0:* users:((\"postgres\",pid=871,fd=6)) uid:999 ino:4242 sk:1 cgroup:/system.slice/postgresql.service <->\n",
+            "LISTEN 0 128 0.0.0.0:22 0.0.0.0:* users:((\"sshd\",pid=700,fd=3)) ino:1717 sk:2 cgroup:/system.slice/ssh.service <->\n",
+            "LISTEN 0 128 0.0.0.0:8080 0.0.0.0:* users:((\"app\",pid=900,fd=3)) uid:1234 ino:1818 sk:3 <->\n"
+        );
+        let users = HashMap::from([(0, "root".to_string()), (999, "postgres".to_string())]);
+        let parsed = parse_ss_output(raw, Protocol::Tcp, &users);
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].port, 22);
+        assert_eq!(parsed[0].user, "root");
+        assert_eq!(parsed[1].command, "postgres");
+        assert_eq!(parsed[1].user, "postgres");
+        assert_eq!(parsed[2].user, "1234");
     }
 
     #[test]
-    fn test_parse_ss_process_info_complete() {
-        let raw = "users:((\"postgres\",pid=1178,fd=7))";
-        let (pid, command) = parse_ss_process_info(raw);
-        assert_eq!(pid, Some(1178));
-        assert_eq!(command, "postgres");
+    fn test_parse_ss_output_udp() {
+        let raw = "UNCONN 0 0 127.0.0.53%lo:53 0.0.0.0:* users:((\"systemd-resolve\",pid=728,fd=13)) uid:101 ino:3131 sk:4 <->\n";
+        let users = HashMap::from([(101, "systemd-resolve".to_string())]);
+        let parsed = parse_ss_output(raw, Protocol::Udp, &users);
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].protocol, Protocol::Udp);
+        assert_eq!(parsed[0].port, 53);
+        assert_eq!(parsed[0].user, "systemd-resolve");
     }
 
     #[test]
-    fn test_parse_ss_process_info_missing() {
-        let (pid, command) = parse_ss_process_info("");
-        assert_eq!(pid, None);
-        assert_eq!(command, "unknown");
+    fn test_parse_ss_uid_without_extended_info() {
+        assert_eq!(parse_ss_uid("users:((\"nginx\",pid=1000,fd=7))"), None);
+        assert_eq!(parse_ss_uid("ino:1717 sk:2"), Some(0));
+        assert_eq!(parse_ss_uid("uid:33 ino:1717"), Some(33));
     }
 
     #[test]
-    fn test_parse_ss_output_variants() {
-        let raw = concat!(
-            "LISTEN 0 128 *:22 *:*\n",
-            "LISTEN 0 4096 127.0.0.53%lo:53 0.0.0.0:* users:((\"systemd-resolve\",pid=728,fd=14))\n",
-            "LISTEN 0 511 [::]:443 [::]:* users:((\"nginx\",pid=1000,fd=7))\n"
-        );
-        let parsed = parse_ss_output(raw);
+    fn test_parse_passwd_maps_uids() {
+        let raw = "# comment\nroot:x:0:0:root:/root:/bin/bash\npostgres:x:999:999::/var/lib/postgresql:/bin/bash\ntoor:x:0:0::/root:/bin/sh\nbroken\n";
+        let users = parse_passwd(raw);
 
-        assert_eq!(parsed.len(), 3);
-        assert!(parsed.iter().any(|v| v.port == 22 && v.pid.is_none()));
-        assert!(parsed
-            .iter()
-            .any(|v| v.port == 53 && v.pid == Some(728) && v.command == "systemd-resolve"));
-        assert!(parsed
-            .iter()
-            .any(|v| v.port == 443 && v.pid == Some(1000) && v.command == "nginx"));
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[&0], "root");
+        assert_eq!(users[&999], "postgres");
     }
 
     #[test]
@@ -779,17 +7639,21 @@ mod tests {
         let listeners = vec![
             Listener {
                 port: 80,
+                protocol: Protocol::Tcp,
                 pid: Some(10),
                 command: "nginx".to_string(),
                 user: "root".to_string(),
                 endpoint: "*:80".to_string(),
+                netns: None,
             },
             Listener {
                 port: 80,
+                protocol: Protocol::Tcp,
                 pid: Some(10),
                 command: "nginx".to_string(),
                 user: "root".to_string(),
                 endpoint: "[::]:80".to_string(),
+                netns: None,
             },
         ];
 
@@ -805,37 +7669,37 @@ mod tests {
 
     #[test]
     fn test_infer_role_by_command_postgres() {
-        let role = infer_role(9999, "postgres");
+        let role = infer_role(9999, Protocol::Tcp, "postgres");
         assert_eq!(role.description, "PostgreSQL database");
-        assert_eq!(role.confidence, "high");
+        assert_eq!(role.confidence, Confidence::High);
     }
 
     #[test]
     fn test_infer_role_by_command_redis() {
-        let role = infer_role(9999, "redis-server");
+        let role = infer_role(9999, Protocol::Tcp, "redis-server");
         assert_eq!(role.description, "Redis cache or message broker");
-        assert_eq!(role.confidence, "high");
+        assert_eq!(role.confidence, Confidence::High);
     }
 
     #[test]
     fn test_infer_role_by_port_ssh() {
-        let role = infer_role(22, "sshd");
+        let role = infer_role(22, Protocol::Tcp, "sshd");
         assert_eq!(role.description, "SSH service");
-        assert_eq!(role.confidence, "medium");
+        assert_eq!(role.confidence, Confidence::Medium);
     }
 
     #[test]
     fn test_infer_role_by_port_http() {
-        let role = infer_role(80, "httpd");
+        let role = infer_role(80, Protocol::Tcp, "httpd");
         assert_eq!(role.description, "HTTP web service");
-        assert_eq!(role.confidence, "medium");
+        assert_eq!(role.confidence, Confidence::Medium);
     }
 
     #[test]
     fn test_infer_role_unknown() {
-        let role = infer_role(9999, "myapp");
+        let role = infer_role(9999, Protocol::Tcp, "myapp");
         assert_eq!(role.description, "Unknown application service");
-        assert_eq!(role.confidence, "medium");
+        assert_eq!(role.confidence, Confidence::Medium);
     }
 
     #[test]
@@ -861,6 +7725,216 @@ mod tests {
         assert_eq!(parsed[0].user, "0");
     }
 
+    #[test]
+    fn test_parse_lsof_output_udp_skips_connected() {
+        let raw = "p728\ncsystemd-resolve\nLsystemd-resolve\nf13\nPUDP\nn127.0.0.53:53\nf20\nPUDP\nn127.0.0.1:50000->127.0.0.53:53\np10\ncnginx\nLroot\nf7\nPTCP\nn*:80\n";
+        let parsed = parse_lsof_output(raw);
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].port, 53);
+        assert_eq!(parsed[0].protocol, Protocol::Udp);
+        assert_eq!(parsed[1].port, 80);
+        assert_eq!(parsed[1].protocol, Protocol::Tcp);
+    }
+
+    #[test]
+    fn test_lsof_protocol_args() {
+        assert_eq!(
+            lsof_protocol_args(&[Protocol::Tcp, Protocol::Udp]),
+            vec!["-iTCP", "-sTCP:LISTEN", "-iUDP"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_listeners_keeps_protocols_apart() {
+        let listener = |protocol| Listener {
+            port: 53,
+            protocol,
+            pid: Some(728),
+            command: "dnsmasq".to_string(),
+            user: "nobody".to_string(),
+            endpoint: "127.0.0.1:53".to_string(),
+            netns: None,
+        };
+        let aggregated = aggregate_listeners(&[listener(Protocol::Udp), listener(Protocol::Tcp)]);
+
+        assert_eq!(aggregated.len(), 2);
+        assert_eq!(aggregated[0].protocol, Protocol::Tcp);
+        assert_eq!(aggregated[1].protocol, Protocol::Udp);
+    }
+
+    #[test]
+    fn test_aggregate_listeners_keeps_unix_sockets_apart() {
+        let socket = |pid, command: &str, path: &str| Listener {
+            port: 0,
+            protocol: Protocol::Unix,
+            pid,
+            command: command.to_string(),
+            user: "root".to_string(),
+            endpoint: path.to_string(),
+            netns: None,
+        };
+        let aggregated = aggregate_listeners(&[
+            // Owners of other users' sockets are unknown without privileges
+            socket(None, "unknown", "/run/user/1000/bus"),
+            socket(None, "unknown", "/tmp/.X11-unix/X0"),
+            // PID 1 holding two sockets for socket activation
+            socket(Some(1), "systemd", "/run/dbus/system_bus_socket"),
+            socket(Some(1), "systemd", "/run/docker.sock"),
+        ]);
+
+        assert_eq!(aggregated.len(), 4);
+        assert!(aggregated.iter().all(|l| l.endpoints.len() == 1));
+        let role_of = |path: &str| {
+            aggregated
+                .iter()
+                .find(|l| l.endpoint == path)
+                .map(|l| l.role.description.to_string())
+                .unwrap()
+        };
+        assert_eq!(role_of("/run/docker.sock"), "Docker engine API");
+        assert_eq!(role_of("/run/dbus/system_bus_socket"), "D-Bus message bus");
+    }
+
+    #[test]
+    fn test_infer_role_by_udp_port() {
+        let role = infer_role(53, Protocol::Udp, "myresolver");
+        assert_eq!(role.description, "DNS resolver or server");
+        assert_eq!(
+            infer_role(53, Protocol::Tcp, "myresolver").description,
+            "Unknown application service"
+        );
+    }
+
+    fn tcp_listener(port: u16, endpoints: &[&str]) -> AggregatedListener {
+        AggregatedListener {
+            port,
+            protocol: Protocol::Tcp,
+            pid: Some(871),
+            command: "postgres".to_string(),
+            user: "postgres".to_string(),
+            endpoint: endpoints[0].to_string(),
+            endpoints: endpoints.iter().map(|e| e.to_string()).collect(),
+            role: infer_role(port, Protocol::Tcp, "postgres"),
+            service: None,
+            connections: None,
+            netns: None,
+            container: None,
+            unit: None,
+            socket_activation: None,
+            probe: None,
+            http: None,
+            tls: None,
+            cmdline: None,
+            exe: None,
+            cwd: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_proc_net_established_normalizes_mapped() {
+        let raw = concat!(
+            "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+            "   0: 0000000000000000FFFF00000100007F:1538 0000000000000000FFFF00000100007F:C350 01 00000000:00000000 00:00000000 00000000   999        0 7001 1 0000000000000000 20 4 0 10 -1\n",
+            "   1: 00000000000000000000000000000000:1538 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000   999        0 7002 1 0000000000000000 100 0 0 10 0\n"
+        );
+        let parsed = parse_proc_net_established(raw);
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].0, "127.0.0.1:5432".parse::<SocketAddr>().unwrap());
+        assert_eq!(
+            parsed[0].1,
+            "127.0.0.1:50000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(parsed[0].2, 7001);
+    }
+
+    #[test]
+    fn test_parse_lsof_connections() {
+        let raw = "p871\ncpostgres\nf9\nPTCP\nn127.0.0.1:5432->127.0.0.1:50000\np4000\ncpsql\nf3\nPTCP\nn127.0.0.1:50000->127.0.0.1:5432\n";
+        let parsed = parse_lsof_connections(raw);
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].pid, Some(4000));
+        assert_eq!(parsed[1].command.as_deref(), Some("psql"));
+        assert_eq!(parsed[1].remote.port(), 5432);
+    }
+
+    #[test]
+    fn test_attach_connections_groups_by_remote() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let connections = vec![
+            Connection {
+                local: addr("127.0.0.1:5432"),
+                remote: addr("127.0.0.1:50000"),
+                pid: Some(871),
+                command: Some("postgres".to_string()),
+            },
+            Connection {
+                local: addr("127.0.0.1:50000"),
+                remote: addr("127.0.0.1:5432"),
+                pid: Some(4000),
+                command: Some("psql".to_string()),
+            },
+            Connection {
+                local: addr("10.0.0.2:5432"),
+                remote: addr("10.0.0.9:41000"),
+                pid: Some(872),
+                command: Some("postgres".to_string()),
+            },
+            Connection {
+                local: addr("10.0.0.2:5432"),
+                remote: addr("10.0.0.9:41002"),
+                pid: Some(873),
+                command: Some("postgres".to_string()),
+            },
+        ];
+        let mut aggregated = vec![tcp_listener(5432, &["0.0.0.0:5432"])];
+        attach_connections(&mut aggregated, &connections);
+
+        let groups = aggregated[0].connections.as_ref().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].address, "10.0.0.9");
+        assert_eq!(groups[0].count, 2);
+        assert_eq!(groups[0].peers[0].pid, None);
+        assert_eq!(groups[1].address, "127.0.0.1");
+        assert_eq!(groups[1].count, 1);
+        assert_eq!(groups[1].peers[0].pid, Some(4000));
+        assert_eq!(groups[1].peers[0].command.as_deref(), Some("psql"));
+    }
+
+    #[test]
+    fn test_listener_accepts_specific_and_wildcard() {
+        let local = "127.0.0.1:5432".parse::<SocketAddr>().unwrap();
+        assert!(listener_accepts(&["127.0.0.1:5432".to_string()], local));
+        assert!(listener_accepts(&["[::]:5432".to_string()], local));
+        assert!(listener_accepts(&["*:5432".to_string()], local));
+        assert!(!listener_accepts(&["10.0.0.2:5432".to_string()], local));
+        assert!(!listener_accepts(&["*:6432".to_string()], local));
+    }
+
+    #[test]
+    fn test_build_connection_lines() {
+        let groups = vec![ConnectionGroup {
+            address: "127.0.0.1".to_string(),
+            count: 1,
+            peers: vec![ConnectionPeer {
+                endpoint: "127.0.0.1:50000".to_string(),
+                pid: Some(4000),
+                command: Some("psql".to_string()),
+            }],
+        }];
+
+        assert_eq!(
+            build_connection_lines(&groups),
+            vec!["  <- 127.0.0.1 x1: 127.0.0.1:50000 (psql, pid 4000)".to_string()]
+        );
+        assert_eq!(
+            build_connection_lines(&[]),
+            vec!["  <- no established connections".to_string()]
+        );
+    }
+
     #[test]
     fn test_pid_display_some() {
         assert_eq!(pid_display(Some(123)), "123");